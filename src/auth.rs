@@ -1,4 +1,5 @@
 use std::{
+  fmt::Write,
   future::{ready, Ready},
   sync::Arc,
 };
@@ -6,18 +7,130 @@ use std::{
 use actix_web::{
   body::EitherBody,
   dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+  http::header::{HeaderValue, AUTHORIZATION},
   web::Data,
-  Error,
+  Error, HttpMessage,
 };
 use futures_util::future::LocalBoxFuture;
+use log::{info, warn};
+use ring::digest::{digest, SHA256};
+use subtle::ConstantTimeEq;
 
 use crate::{
-  config::Config,
-  helpers::{bad_request, unauthorized},
+  config::{AdminToken, Config, Permission, TurboToken},
+  error::Error as AppError,
+  metrics::AUTH_FAILURES,
 };
 
 type AppConfigData = Data<Arc<Config>>;
 
+/// Prefix of tokens configured as their hash rather than in clear, see [`hash_token`].
+pub const HASHED_TOKEN_PREFIX: &str = "sha256-";
+
+/// Hashes `token` so it can be configured in place of the token itself, keeping
+/// usable credentials out of the configuration.
+pub fn hash_token(token: &str) -> String {
+  digest(&SHA256, token.as_bytes()).as_ref().iter().fold(
+    HASHED_TOKEN_PREFIX.to_string(),
+    |mut hash, byte| {
+      let _ = write!(hash, "{:02x}", byte);
+      hash
+    },
+  )
+}
+
+/// Whether two configured tokens, each in clear or as its hash, are the same token.
+pub fn same_token(a: &str, b: &str) -> bool {
+  match (
+    a.starts_with(HASHED_TOKEN_PREFIX),
+    b.starts_with(HASHED_TOKEN_PREFIX),
+  ) {
+    (true, false) => a == hash_token(b),
+    (false, true) => hash_token(a) == b,
+    _ => a == b,
+  }
+}
+
+/// The identity resolved from a valid turbo token, handlers get it via `ReqData<Principal>`.
+#[derive(Debug, Clone)]
+pub struct Principal {
+  teams: Option<Vec<String>>,
+  permissions: Vec<Permission>,
+}
+
+impl Principal {
+  /// Whether the token is scoped to `team`, or to every team.
+  pub fn can_access(&self, team: &str) -> bool {
+    self
+      .teams
+      .as_ref()
+      .map_or(true, |teams| teams.iter().any(|t| t == team))
+  }
+
+  /// Checks that the principal can perform `permission` on the artifacts of `team`.
+  pub fn authorize(&self, team: &str, permission: Permission) -> Result<(), AppError> {
+    if !self.can_access(team) {
+      AUTH_FAILURES.with_label_values(&["forbidden_team"]).inc();
+      return Err(AppError::Forbidden(format!(
+        "Token is not allowed to access team {}",
+        team
+      )));
+    }
+    if !self.permissions.contains(&permission) {
+      AUTH_FAILURES
+        .with_label_values(&["forbidden_permission"])
+        .inc();
+      return Err(AppError::Forbidden(format!(
+        "Token doesn't have {} permission",
+        permission
+      )));
+    }
+    Ok(())
+  }
+}
+
+impl From<&TurboToken> for Principal {
+  fn from(token: &TurboToken) -> Self {
+    Principal {
+      teams: token.teams.clone(),
+      permissions: token.permissions.clone(),
+    }
+  }
+}
+
+/// The identity resolved from a valid admin token, admin handlers get it via
+/// `ReqData<AdminPrincipal>`.
+#[derive(Debug, Clone)]
+pub struct AdminPrincipal {
+  pub name: String,
+  permissions: Vec<Permission>,
+}
+
+impl AdminPrincipal {
+  /// Checks that the principal can perform `permission` through the admin API.
+  pub fn authorize(&self, permission: Permission) -> Result<(), AppError> {
+    if !self.permissions.contains(&permission) {
+      AUTH_FAILURES
+        .with_label_values(&["forbidden_permission"])
+        .inc();
+      return Err(AppError::Forbidden(format!(
+        "Admin token doesn't have {} permission",
+        permission
+      )));
+    }
+    Ok(())
+  }
+}
+
+impl From<&AdminToken> for AdminPrincipal {
+  fn from(token: &AdminToken) -> Self {
+    AdminPrincipal {
+      name: token.name.clone(),
+      permissions: token.permissions.clone(),
+    }
+  }
+}
+
 pub struct Auth;
 
 impl<S, B> Transform<S, ServiceRequest> for Auth
@@ -53,38 +166,153 @@ where
   forward_ready!(service);
 
   fn call(&self, request: ServiceRequest) -> Self::Future {
-    let turbo_tokens = request
-      .app_data::<AppConfigData>()
-      .map(|data| data.turbo_tokens.clone());
-
-    let turbo_tokens = match turbo_tokens {
-      Some(tokens) => tokens,
-      None => {
-        let (req, _pl) = request.into_parts();
-        let response =
-          bad_request("Missing TURBO_TOKENS in the environment".to_string()).map_into_right_body();
-        return Box::pin(async { Ok(ServiceResponse::new(req, response)) });
+    match authenticate(&request) {
+      Ok(principal) => {
+        request.extensions_mut().insert(principal);
       }
-    };
-
-    let auth_header = request.headers().get("Authorization");
-    let auth_header_value = match auth_header {
-      None => {
-        let (req, _pl) = request.into_parts();
-        let response =
-          unauthorized("Missing Authorization header".to_string()).map_into_right_body();
-        return Box::pin(async { Ok(ServiceResponse::new(req, response)) });
+      Err(e) => {
+        let response = request.error_response(e).map_into_right_body();
+        return Box::pin(async { Ok(response) });
       }
-      Some(v) => v.to_str().unwrap().split("Bearer ").collect::<Vec<&str>>()[1],
-    };
-
-    if !turbo_tokens.contains(&auth_header_value.to_string()) {
-      let (req, _pl) = request.into_parts();
-      let response = unauthorized("Invalid Turbo Token".to_string()).map_into_right_body();
-      return Box::pin(async { Ok(ServiceResponse::new(req, response)) });
     }
 
     let res = self.service.call(request);
     Box::pin(async move { res.await.map(ServiceResponse::map_into_left_body) })
   }
 }
+
+/// Guards the admin API, only admin tokens are accepted so turbo tokens handed
+/// to CI jobs can't reach it. Every admin call is logged with its principal.
+pub struct AdminAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for AdminAuth
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = AdminAuthMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ready(Ok(AdminAuthMiddleware { service }))
+  }
+}
+pub struct AdminAuthMiddleware<S> {
+  service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for AdminAuthMiddleware<S>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: 'static,
+{
+  type Response = ServiceResponse<EitherBody<B>>;
+  type Error = Error;
+  type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  forward_ready!(service);
+
+  fn call(&self, request: ServiceRequest) -> Self::Future {
+    let call = format!("{} {}", request.method(), request.uri());
+    let principal = match authenticate_admin(&request) {
+      Ok(principal) => principal,
+      Err(e) => {
+        warn!("Rejected admin call {}: {}", call, e);
+        let response = request.error_response(e).map_into_right_body();
+        return Box::pin(async { Ok(response) });
+      }
+    };
+    let name = principal.name.clone();
+    request.extensions_mut().insert(principal);
+
+    let res = self.service.call(request);
+    Box::pin(async move {
+      let response = res.await?;
+      info!("Admin call {} by {}: {}", call, name, response.status());
+      Ok(response.map_into_left_body())
+    })
+  }
+}
+
+fn config_data(request: &ServiceRequest) -> Result<&AppConfigData, AppError> {
+  request
+    .app_data::<AppConfigData>()
+    .ok_or_else(|| AppError::Invalid("Missing TURBO_TOKENS in the environment".to_string()))
+}
+
+// The bearer token of the request.
+fn presented_token(request: &ServiceRequest) -> Result<&str, AppError> {
+  let auth_header = request.headers().get(AUTHORIZATION).ok_or_else(|| {
+    AUTH_FAILURES.with_label_values(&["missing_header"]).inc();
+    AppError::Unauthorized("Missing Authorization header".to_string())
+  })?;
+  bearer_token(auth_header).inspect_err(|_| {
+    AUTH_FAILURES.with_label_values(&["malformed_header"]).inc();
+  })
+}
+
+fn authenticate(request: &ServiceRequest) -> Result<Principal, AppError> {
+  let config = config_data(request)?;
+  let token = presented_token(request)?;
+  find_token(&config.turbo_tokens, token, |candidate| &candidate.token)
+    .map(Principal::from)
+    .ok_or_else(|| {
+      AUTH_FAILURES.with_label_values(&["invalid_token"]).inc();
+      AppError::Unauthorized("Invalid Turbo Token".to_string())
+    })
+}
+
+fn authenticate_admin(request: &ServiceRequest) -> Result<AdminPrincipal, AppError> {
+  let config = config_data(request)?;
+  let token = presented_token(request)?;
+  find_token(&config.admin_tokens, token, |candidate| &candidate.token)
+    .map(AdminPrincipal::from)
+    .ok_or_else(|| {
+      AUTH_FAILURES
+        .with_label_values(&["invalid_admin_token"])
+        .inc();
+      AppError::Unauthorized("Invalid Admin Token".to_string())
+    })
+}
+
+/// Extracts the token from a `Bearer <token>` header, the scheme is matched case-insensitively.
+fn bearer_token(header: &HeaderValue) -> Result<&str, AppError> {
+  let malformed = || AppError::Unauthorized("Malformed Authorization header".to_string());
+  let value = header.to_str().map_err(|_| malformed())?.trim();
+  let (scheme, token) = value
+    .split_once(|c: char| c.is_ascii_whitespace())
+    .ok_or_else(malformed)?;
+  if !scheme.eq_ignore_ascii_case("bearer") {
+    return Err(AppError::Unauthorized(
+      "Authorization header must use the Bearer scheme".to_string(),
+    ));
+  }
+  let token = token.trim();
+  if token.is_empty() {
+    return Err(malformed());
+  }
+  Ok(token)
+}
+
+// Compares against every token without short-circuiting so the response time doesn't
+// reveal how much of a token matched.
+fn find_token<'a, T>(tokens: &'a [T], token: &str, secret: impl Fn(&T) -> &str) -> Option<&'a T> {
+  let hashed = hash_token(token);
+  let mut found = None;
+  for candidate in tokens {
+    let expected = secret(candidate);
+    let presented = match expected.starts_with(HASHED_TOKEN_PREFIX) {
+      true => hashed.as_str(),
+      false => token,
+    };
+    if bool::from(expected.as_bytes().ct_eq(presented.as_bytes())) {
+      found = Some(candidate);
+    }
+  }
+  found
+}
@@ -0,0 +1,215 @@
+use std::{fmt::Display, io::BufRead, path::Path, sync::Arc};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use clap::{Args, Parser, Subcommand};
+use log::info;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::{
+  auth::hash_token,
+  config::{Config, StorageProvider, DEFAULT_LOG_LEVEL},
+  error::Error,
+  storage::{self, gc::FileGc, retention::RetentionSweeper, StorageStore},
+};
+
+/// Exit code when a command failed, e.g. the storage provider is unreachable.
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code for invalid arguments or configuration, the one clap uses for usage errors.
+pub const EXIT_USAGE: u8 = 2;
+
+// Random bytes of a generated token.
+const TOKEN_LEN: usize = 32;
+
+/// Fast turbo remote cache server.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+  /// TOML config file, environment variables override its settings.
+  #[arg(long, global = true, value_name = "PATH")]
+  pub config: Option<String>,
+  /// File environment variables are loaded from when it exists.
+  #[arg(long, global = true, value_name = "PATH", default_value = ".env")]
+  pub env_file: String,
+  /// Defaults to `serve`.
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+  /// Serve the remote cache.
+  Serve,
+  /// Validate the configuration and check the storage provider can be written and read.
+  Check,
+  /// Run the file cache garbage collection and the retention sweep once.
+  Gc {
+    /// Only log what would be removed.
+    #[arg(long)]
+    dry_run: bool,
+  },
+  /// Copy every stored artifact to another storage provider.
+  Migrate(MigrateArgs),
+  /// Generate and hash turbo tokens.
+  #[command(subcommand)]
+  Token(TokenCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+  /// Storage provider to copy to: s3, azure, gcs, file or memory.
+  #[arg(long, value_name = "PROVIDER")]
+  pub to: StorageProvider,
+  /// Bucket to copy to, defaults to the configured one.
+  #[arg(long, value_name = "NAME")]
+  pub to_bucket: Option<String>,
+  /// Path of the file provider to copy to, defaults to the configured one.
+  #[arg(long, value_name = "PATH")]
+  pub to_fs_path: Option<String>,
+  /// Only log what would be copied.
+  #[arg(long)]
+  pub dry_run: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TokenCommand {
+  /// Print a new random token.
+  Generate,
+  /// Print the hash of a token, which can be configured in place of the token.
+  Hash {
+    /// Token to hash, read from stdin when omitted to keep it out of the shell history.
+    token: Option<String>,
+  },
+}
+
+/// Failure of a command along with the exit code it maps to.
+#[derive(Debug)]
+pub struct CommandError {
+  pub code: u8,
+  pub message: String,
+}
+
+impl CommandError {
+  pub fn usage<T: Display>(message: T) -> Self {
+    CommandError {
+      code: EXIT_USAGE,
+      message: message.to_string(),
+    }
+  }
+
+  pub fn failed<T: Display>(message: T) -> Self {
+    CommandError {
+      code: EXIT_FAILURE,
+      message: message.to_string(),
+    }
+  }
+}
+
+impl From<Error> for CommandError {
+  fn from(e: Error) -> Self {
+    CommandError::failed(e)
+  }
+}
+
+impl From<std::io::Error> for CommandError {
+  fn from(e: std::io::Error) -> Self {
+    CommandError::failed(e)
+  }
+}
+
+/// Loads the `.env` file and the configuration, then initializes the logger at
+/// the configured level.
+pub fn load_config(config_file: Option<&str>, env_file: &str) -> Result<Config, CommandError> {
+  dotenvy::from_path(Path::new(env_file)).ok();
+  let config = Config::load(config_file);
+  let log_level = config
+    .as_ref()
+    .map_or(DEFAULT_LOG_LEVEL, |config| config.log_level.as_str());
+  env_logger::Builder::new().parse_filters(log_level).init();
+  config.map_err(CommandError::usage)
+}
+
+pub async fn check(config: &Config) -> Result<(), CommandError> {
+  info!("Configuration is valid");
+  StorageStore::new(config)?;
+  storage::check(config).await.map_err(|e| {
+    CommandError::failed(format!(
+      "{} storage provider isn't usable: {}",
+      config.storage_provider, e
+    ))
+  })?;
+  info!(
+    "{} storage provider with bucket {} is reachable",
+    config.storage_provider, config.bucket_name
+  );
+  Ok(())
+}
+
+pub async fn gc(config: &Config, dry_run: bool) -> Result<(), CommandError> {
+  let gc = FileGc::from_config(config, dry_run || config.gc_dry_run)?;
+  let storage = Arc::new(StorageStore::new(config)?);
+  let sweeper = RetentionSweeper::from_config(config, storage, dry_run);
+  if gc.is_none() && sweeper.is_none() {
+    return Err(CommandError::usage(
+      "gc requires RETENTION_RULES, or the file storage provider and GC_MAX_AGE or GC_MAX_SIZE",
+    ));
+  }
+  if let Some(gc) = gc {
+    let report = gc.run().await?;
+    info!("File cache garbage collection: {}", report);
+  }
+  if let Some(sweeper) = sweeper {
+    let report = sweeper.run().await?;
+    info!("Retention sweep: {}", report);
+  }
+  Ok(())
+}
+
+pub async fn migrate(config: &Config, args: MigrateArgs) -> Result<(), CommandError> {
+  let mut to = config
+    .clone()
+    .with_storage_provider(args.to)
+    .with_cache_tier(None);
+  if let Some(bucket) = args.to_bucket {
+    to = to.with_bucket_name(bucket);
+  }
+  if let Some(path) = args.to_fs_path {
+    to = to.with_fs_cache_path(path);
+  }
+  let from = StorageStore::new(config)?;
+  let to_storage = StorageStore::new(&to)?;
+  info!(
+    "Migrating {} bucket {} to {} bucket {}",
+    config.storage_provider, config.bucket_name, to.storage_provider, to.bucket_name
+  );
+  let report = from.migrate_to(&to_storage, args.dry_run).await?;
+  info!("Migration: {}", report);
+  Ok(())
+}
+
+pub fn token(command: TokenCommand) -> Result<(), CommandError> {
+  match command {
+    TokenCommand::Generate => {
+      let mut bytes = [0u8; TOKEN_LEN];
+      SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| CommandError::failed("error generating random bytes"))?;
+      println!("{}", URL_SAFE_NO_PAD.encode(bytes));
+    }
+    TokenCommand::Hash { token } => {
+      let token = match token {
+        Some(token) => token,
+        None => {
+          let mut line = String::new();
+          std::io::stdin().lock().read_line(&mut line)?;
+          line
+        }
+      };
+      let token = token.trim();
+      if token.is_empty() {
+        return Err(CommandError::usage("token can't be empty"));
+      }
+      println!("{}", hash_token(token));
+    }
+  }
+  Ok(())
+}
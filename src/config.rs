@@ -1,4 +1,18 @@
-use std::{env::VarError, fmt::Display};
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use crate::{auth::same_token, helpers::validate_team_id, storage::crypto::Keyring};
+
+pub const DEFAULT_MAX_ARTIFACT_SIZE: usize = 104857600;
+pub const DEFAULT_VERIFIED_SIZE: usize = 1048576;
+pub const DEFAULT_MEMORY_MAX_SIZE: usize = 536870912;
+pub const DEFAULT_GC_INTERVAL: Duration = Duration::from_secs(3600);
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+pub const DEFAULT_PRESIGNED_URL_EXPIRY: Duration = Duration::from_secs(300);
+pub const DEFAULT_RETENTION_INTERVAL: Duration = Duration::from_secs(3600);
+pub const DEFAULT_PORT: u16 = 4000;
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+// Longest lifetime of a presigned URL accepted by every cloud provider.
+const MAX_PRESIGNED_URL_EXPIRY: Duration = Duration::from_secs(7 * 86400);
 
 #[derive(Debug, Clone, Default)]
 pub enum StorageProvider {
@@ -10,19 +24,32 @@ pub enum StorageProvider {
   Memory,
 }
 
-impl From<&str> for StorageProvider {
-  fn from(s: &str) -> Self {
+impl FromStr for StorageProvider {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
     match s {
-      "s3" => StorageProvider::S3,
-      "file" => StorageProvider::File,
-      "gcs" => StorageProvider::Gcs,
-      "azure" => StorageProvider::Azure,
-      "memory" => StorageProvider::Memory,
-      _ => panic!("Invalid storage provider"),
+      "s3" => Ok(StorageProvider::S3),
+      "file" => Ok(StorageProvider::File),
+      "gcs" => Ok(StorageProvider::Gcs),
+      "azure" => Ok(StorageProvider::Azure),
+      "memory" => Ok(StorageProvider::Memory),
+      _ => Err(format!(
+        "Invalid storage provider {}, expected s3, file, gcs, azure or memory",
+        s
+      )),
     }
   }
 }
 
+impl StorageProvider {
+  /// Whether objects are listed in lexicographical order, which buckets and the
+  /// memory store guarantee but not the file system.
+  pub fn lists_in_order(&self) -> bool {
+    !matches!(self, StorageProvider::File)
+  }
+}
+
 impl Display for StorageProvider {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
@@ -35,40 +62,629 @@ impl Display for StorageProvider {
   }
 }
 
+/// Local tier put in front of a remote storage provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheTier {
+  Memory,
+  File,
+}
+
+impl FromStr for CacheTier {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "memory" => Ok(CacheTier::Memory),
+      "file" => Ok(CacheTier::File),
+      _ => Err(format!("Invalid cache tier {}, expected memory or file", s)),
+    }
+  }
+}
+
+impl Display for CacheTier {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      CacheTier::Memory => write!(f, "memory"),
+      CacheTier::File => write!(f, "file"),
+    }
+  }
+}
+
+/// Compression applied to artifacts before they are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+  /// Artifacts are stored as they are received.
+  #[default]
+  None,
+  Zstd,
+}
+
+impl FromStr for Compression {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "none" => Ok(Compression::None),
+      "zstd" => Ok(Compression::Zstd),
+      _ => Err(format!("Invalid compression {}, expected none or zstd", s)),
+    }
+  }
+}
+
+/// How artifacts written to a tiered store reach the remote provider.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WritePolicy {
+  /// The remote provider is written before the request completes.
+  #[default]
+  WriteThrough,
+  /// Only the local tier is written before the request completes, the remote
+  /// provider is written in the background.
+  WriteBack,
+}
+
+impl FromStr for WritePolicy {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "write-through" => Ok(WritePolicy::WriteThrough),
+      "write-back" => Ok(WritePolicy::WriteBack),
+      _ => Err(format!(
+        "Invalid write policy {}, expected write-through or write-back",
+        s
+      )),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+  Read,
+  Write,
+}
+
+impl FromStr for Permission {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "read" => Ok(Permission::Read),
+      "write" => Ok(Permission::Write),
+      _ => Err(format!("Invalid permission {}, expected read or write", s)),
+    }
+  }
+}
+
+impl Display for Permission {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Permission::Read => write!(f, "read"),
+      Permission::Write => write!(f, "write"),
+    }
+  }
+}
+
+/// A turbo token along with the teams and permissions it grants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurboToken {
+  pub token: String,
+  /// Teams the token can access, `None` grants access to every team.
+  pub teams: Option<Vec<String>>,
+  pub permissions: Vec<Permission>,
+}
+
+/// A plain token can read and write every team.
+impl From<String> for TurboToken {
+  fn from(token: String) -> Self {
+    TurboToken {
+      token,
+      teams: None,
+      permissions: vec![Permission::Read, Permission::Write],
+    }
+  }
+}
+
+/// Parses `token[:teams[:permissions]]` where `teams` is `*` or a `|` separated
+/// list of teams and `permissions` is a `|` separated list of `read` and `write`,
+/// e.g. `my-token:team-a|team-b:read`.
+impl FromStr for TurboToken {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut parts = s.split(':').map(str::trim);
+    let token = match parts.next() {
+      Some(token) if !token.is_empty() => token.to_string(),
+      _ => return Err("Turbo token can't be empty".to_string()),
+    };
+    let teams = match parts.next() {
+      None | Some("*") => None,
+      Some(teams) => {
+        let teams: Vec<String> = teams
+          .split('|')
+          .map(str::trim)
+          .filter(|team| !team.is_empty())
+          .map(str::to_string)
+          .collect();
+        if teams.is_empty() {
+          return Err("Turbo token teams can't be empty, use * for every team".to_string());
+        }
+        Some(teams)
+      }
+    };
+    let permissions = match parts.next() {
+      None => vec![Permission::Read, Permission::Write],
+      Some(permissions) => permissions
+        .split('|')
+        .map(|p| p.trim().parse())
+        .collect::<Result<Vec<_>, _>>()?,
+    };
+    if parts.next().is_some() {
+      return Err("Turbo token must look like token[:teams[:permissions]]".to_string());
+    }
+    Ok(TurboToken {
+      token,
+      teams,
+      permissions,
+    })
+  }
+}
+
+/// A token of the admin API, distinct from the turbo tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminToken {
+  /// Who the token belongs to, logged along every admin call.
+  pub name: String,
+  pub token: String,
+  pub permissions: Vec<Permission>,
+}
+
+/// Parses `name:token[:permissions]` where `permissions` is a `|` separated list
+/// of `read` and `write`, e.g. `alice:my-token:read`.
+impl FromStr for AdminToken {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut parts = s.split(':').map(str::trim);
+    let name = match parts.next() {
+      Some(name) if !name.is_empty() => name.to_string(),
+      _ => return Err("Admin token name can't be empty".to_string()),
+    };
+    let token = match parts.next() {
+      Some(token) if !token.is_empty() => token.to_string(),
+      _ => return Err(format!("Admin token of {} can't be empty", name)),
+    };
+    let permissions = match parts.next() {
+      None => vec![Permission::Read, Permission::Write],
+      Some(permissions) => permissions
+        .split('|')
+        .map(|p| p.trim().parse())
+        .collect::<Result<Vec<_>, _>>()?,
+    };
+    if parts.next().is_some() {
+      return Err("Admin token must look like name:token[:permissions]".to_string());
+    }
+    Ok(AdminToken {
+      name,
+      token,
+      permissions,
+    })
+  }
+}
+
+/// Every problem found while loading the configuration, reported together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError(pub Vec<String>);
+
+impl Display for ConfigError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Invalid configuration:")?;
+    for problem in &self.0 {
+      write!(f, "\n  - {}", problem)?;
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Storage limits of a team, enforced when artifacts are uploaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TeamQuota {
+  /// Team the quota applies to, `None` for every team without its own quota.
+  pub team: Option<String>,
+  /// Maximum bytes stored by the team, `None` for no limit.
+  pub max_bytes: Option<u64>,
+  /// Maximum number of artifacts stored by the team, `None` for no limit.
+  pub max_objects: Option<u64>,
+}
+
+/// Parses `team:max_bytes[:max_objects]` where `team` is `*` for every team
+/// without its own quota and an empty limit means no limit, e.g. `team-a:1073741824:1000`.
+impl FromStr for TeamQuota {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut parts = s.split(':').map(str::trim);
+    let team = match parts.next() {
+      Some("*") => None,
+      Some(team) if !team.is_empty() => {
+        validate_team_id(team).map_err(|e| format!("Quota team {} is invalid: {}", team, e))?;
+        Some(team.to_string())
+      }
+      _ => return Err("Quota team can't be empty, use * for every team".to_string()),
+    };
+    let mut limit = |name: &str| match parts.next() {
+      None | Some("") => Ok(None),
+      Some(limit) => limit
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("Quota {} {} isn't a valid number", name, limit)),
+    };
+    let max_bytes = limit("max bytes")?;
+    let max_objects = limit("max objects")?;
+    if parts.next().is_some() {
+      return Err("Quota must look like team:max_bytes[:max_objects]".to_string());
+    }
+    if max_bytes.is_none() && max_objects.is_none() {
+      return Err(format!("Quota of {} doesn't set any limit", s));
+    }
+    Ok(TeamQuota {
+      team,
+      max_bytes,
+      max_objects,
+    })
+  }
+}
+
+/// How long artifacts of the matching teams are kept, enforced by the retention sweeper.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionRule {
+  /// Team id or slug the rule applies to, `*` matches any characters (e.g. `pr-*`).
+  pub teams: String,
+  /// Artifacts written longer than this ago are removed.
+  pub max_age: Option<Duration>,
+  /// Oldest artifacts are removed until the team fits in this many bytes.
+  pub max_size: Option<u64>,
+  /// Only this many most recently written artifacts are kept.
+  pub keep_last: Option<usize>,
+}
+
+impl RetentionRule {
+  pub fn matches(&self, team: &str) -> bool {
+    let mut parts = self.teams.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = team.strip_prefix(first) else {
+      return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+      // no wildcard, the whole team must match
+      return rest.is_empty();
+    };
+    for part in parts {
+      match rest.find(part) {
+        Some(index) => rest = &rest[index + part.len()..],
+        None => return false,
+      }
+    }
+    rest.ends_with(last)
+  }
+}
+
+/// Parses `teams:max_age[:max_size[:keep_last]]` where an empty limit means no
+/// limit, e.g. `release-*:90d` or `pr-*:7d::100`.
+impl FromStr for RetentionRule {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut parts = s.split(':').map(str::trim);
+    let teams = match parts.next() {
+      Some(teams) if !teams.is_empty() => teams.to_string(),
+      _ => return Err("Retention rule teams can't be empty, use * for every team".to_string()),
+    };
+    // wildcards aside, the pattern must only contain characters team ids are made of
+    teams
+      .split('*')
+      .filter(|part| !part.is_empty())
+      .try_for_each(validate_team_id)
+      .map_err(|e| format!("Retention rule teams {} are invalid: {}", teams, e))?;
+    let max_age = match parts.next() {
+      None | Some("") => None,
+      Some(age) => Some(parse_duration(age)?),
+    };
+    let max_size = match parts.next() {
+      None | Some("") => None,
+      Some(size) => Some(number(size)?),
+    };
+    let keep_last = match parts.next() {
+      None | Some("") => None,
+      Some(count) => Some(number(count)?),
+    };
+    if parts.next().is_some() {
+      return Err("Retention rule must look like teams:max_age[:max_size[:keep_last]]".to_string());
+    }
+    if max_age.is_none() && max_size.is_none() && keep_last.is_none() {
+      return Err(format!("Retention rule {} doesn't set any limit", s));
+    }
+    Ok(RetentionRule {
+      teams,
+      max_age,
+      max_size,
+      keep_last,
+    })
+  }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
-  pub turbo_tokens: Vec<String>,
+  pub port: u16,
+  pub log_level: String,
+  pub turbo_tokens: Vec<TurboToken>,
+  pub admin_tokens: Vec<AdminToken>,
   pub storage_provider: StorageProvider,
   pub fs_cache_path: String,
   pub bucket_name: String,
+  pub max_artifact_size: usize,
+  pub verified_size: usize,
+  pub events_file: Option<String>,
+  pub memory_max_size: usize,
+  pub gc_max_age: Option<Duration>,
+  pub gc_max_size: Option<u64>,
+  pub gc_interval: Duration,
+  pub gc_dry_run: bool,
+  pub cache_tier: Option<CacheTier>,
+  pub cache_write_policy: WritePolicy,
+  pub compression: Compression,
+  pub compression_level: i32,
+  pub encryption_key_file: Option<String>,
+  pub presigned_urls: bool,
+  pub presigned_url_expiry: Duration,
+  pub quotas: Vec<TeamQuota>,
+  pub quota_evict: bool,
+  pub retention_rules: Vec<RetentionRule>,
+  pub retention_interval: Duration,
+  pub retention_dry_run: bool,
 }
 
 impl Default for Config {
   fn default() -> Self {
     Config {
+      port: DEFAULT_PORT,
+      log_level: DEFAULT_LOG_LEVEL.to_string(),
       turbo_tokens: vec![],
+      admin_tokens: vec![],
       storage_provider: StorageProvider::Memory,
       fs_cache_path: std::env::temp_dir()
         .to_str()
         .expect("error getting temp dir")
         .to_string(),
       bucket_name: "cache".to_string(),
+      max_artifact_size: DEFAULT_MAX_ARTIFACT_SIZE,
+      verified_size: DEFAULT_VERIFIED_SIZE,
+      events_file: None,
+      memory_max_size: DEFAULT_MEMORY_MAX_SIZE,
+      gc_max_age: None,
+      gc_max_size: None,
+      gc_interval: DEFAULT_GC_INTERVAL,
+      gc_dry_run: false,
+      cache_tier: None,
+      cache_write_policy: WritePolicy::default(),
+      compression: Compression::default(),
+      compression_level: DEFAULT_COMPRESSION_LEVEL,
+      encryption_key_file: None,
+      presigned_urls: false,
+      presigned_url_expiry: DEFAULT_PRESIGNED_URL_EXPIRY,
+      quotas: vec![],
+      quota_evict: false,
+      retention_rules: vec![],
+      retention_interval: DEFAULT_RETENTION_INTERVAL,
+      retention_dry_run: false,
     }
   }
 }
 
 impl Config {
-  pub fn from_env() -> Result<Self, VarError> {
-    Ok(Config {
-      turbo_tokens: get_turbo_tokens(),
-      storage_provider: get_storage_provider(),
-      fs_cache_path: get_fs_cache_path(),
-      bucket_name: get_bucket_name(),
-    })
+  /// Loads the configuration from environment variables only.
+  pub fn from_env() -> Result<Self, ConfigError> {
+    Config::load(None)
+  }
+
+  /// Loads the TOML config file at `path` when given, environment variables
+  /// override the values it sets.
+  pub fn load(path: Option<&str>) -> Result<Self, ConfigError> {
+    let file = match path {
+      Some(path) => {
+        let content = std::fs::read_to_string(path)
+          .map_err(|e| ConfigError(vec![format!("Error reading config file {}: {}", path, e)]))?;
+        Some((path, content))
+      }
+      None => None,
+    };
+    let file = file
+      .as_ref()
+      .map(|(path, content)| (*path, content.as_str()));
+    Config::from_sources(file, &|var| std::env::var(var).ok())
+  }
+
+  // Builds the config from a `(path, content)` config file and `env`, a lookup
+  // of environment variables.
+  fn from_sources(
+    file: Option<(&str, &str)>,
+    env: &dyn Fn(&str) -> Option<String>,
+  ) -> Result<Self, ConfigError> {
+    let (path, table) = match file {
+      Some((path, content)) => {
+        let table = content
+          .parse::<toml::Table>()
+          .map_err(|e| ConfigError(vec![format!("Invalid config file {}: {}", path, e)]))?;
+        (path, table)
+      }
+      None => ("", toml::Table::new()),
+    };
+    let mut s = Sources {
+      table,
+      path,
+      env,
+      known: vec![],
+      errors: vec![],
+    };
+    let defaults = Config::default();
+    let config = Config {
+      port: s
+        .parse("server", "port", "PORT", number)
+        .unwrap_or(defaults.port),
+      events_file: s.parse("server", "events_file", "EVENTS_FILE", string),
+      log_level: s
+        .parse("logging", "level", "RUST_LOG", string)
+        .unwrap_or(defaults.log_level),
+      turbo_tokens: s
+        .required("auth", "turbo_tokens", "TURBO_TOKENS", parse_turbo_tokens)
+        .unwrap_or_default(),
+      admin_tokens: s
+        .parse("auth", "admin_tokens", "ADMIN_TOKENS", parse_list)
+        .unwrap_or_default(),
+      max_artifact_size: s
+        .parse("limits", "max_artifact_size", "MAX_ARTIFACT_SIZE", number)
+        .unwrap_or(defaults.max_artifact_size),
+      verified_size: s
+        .parse("limits", "verified_size", "VERIFIED_SIZE", number)
+        .unwrap_or(defaults.verified_size),
+      storage_provider: s
+        .parse("storage", "provider", "STORAGE_PROVIDER", str::parse)
+        .unwrap_or(defaults.storage_provider),
+      bucket_name: s
+        .parse("storage", "bucket_name", "BUCKET_NAME", string)
+        .unwrap_or(defaults.bucket_name),
+      fs_cache_path: s
+        .parse("storage", "fs_path", "FS_PATH", string)
+        .unwrap_or(defaults.fs_cache_path),
+      memory_max_size: s
+        .parse("storage", "memory_max_size", "MEMORY_MAX_SIZE", number)
+        .unwrap_or(defaults.memory_max_size),
+      cache_tier: s.parse("storage", "cache_tier", "CACHE_TIER", str::parse),
+      cache_write_policy: s
+        .parse(
+          "storage",
+          "cache_write_policy",
+          "CACHE_WRITE_POLICY",
+          str::parse,
+        )
+        .unwrap_or(defaults.cache_write_policy),
+      compression: s
+        .parse("storage", "compression", "COMPRESSION", str::parse)
+        .unwrap_or(defaults.compression),
+      compression_level: s
+        .parse("storage", "compression_level", "COMPRESSION_LEVEL", number)
+        .unwrap_or(defaults.compression_level),
+      encryption_key_file: s.parse(
+        "storage",
+        "encryption_key_file",
+        "ENCRYPTION_KEY_FILE",
+        string,
+      ),
+      presigned_urls: s
+        .parse("storage", "presigned_urls", "PRESIGNED_URLS", boolean)
+        .unwrap_or(defaults.presigned_urls),
+      presigned_url_expiry: s
+        .parse(
+          "storage",
+          "presigned_url_expiry",
+          "PRESIGNED_URL_EXPIRY",
+          parse_duration,
+        )
+        .unwrap_or(defaults.presigned_url_expiry),
+      quotas: s
+        .parse("quotas", "teams", "TEAM_QUOTAS", parse_list)
+        .unwrap_or_default(),
+      quota_evict: s
+        .parse("quotas", "evict", "QUOTA_EVICT", boolean)
+        .unwrap_or(defaults.quota_evict),
+      retention_rules: s
+        .parse("retention", "rules", "RETENTION_RULES", parse_list)
+        .unwrap_or_default(),
+      retention_interval: s
+        .parse(
+          "retention",
+          "interval",
+          "RETENTION_INTERVAL",
+          parse_duration,
+        )
+        .unwrap_or(defaults.retention_interval),
+      retention_dry_run: s
+        .parse("retention", "dry_run", "RETENTION_DRY_RUN", boolean)
+        .unwrap_or(defaults.retention_dry_run),
+      gc_max_age: s.parse("gc", "max_age", "GC_MAX_AGE", parse_duration),
+      gc_max_size: s.parse("gc", "max_size", "GC_MAX_SIZE", number),
+      gc_interval: s
+        .parse("gc", "interval", "GC_INTERVAL", parse_duration)
+        .unwrap_or(defaults.gc_interval),
+      gc_dry_run: s
+        .parse("gc", "dry_run", "GC_DRY_RUN", boolean)
+        .unwrap_or(defaults.gc_dry_run),
+    };
+    s.check_unknown();
+    config.validate(&mut s.errors);
+    match s.errors.is_empty() {
+      true => Ok(config),
+      false => Err(ConfigError(s.errors)),
+    }
+  }
+
+  // Checks the values that parsed but can't work together or with the providers.
+  fn validate(&self, errors: &mut Vec<String>) {
+    if self.bucket_name.is_empty() {
+      errors.push("BUCKET_NAME can't be empty".to_string());
+    }
+    for admin in &self.admin_tokens {
+      let reused = self
+        .turbo_tokens
+        .iter()
+        .any(|turbo| same_token(&admin.token, &turbo.token));
+      if reused {
+        errors.push(format!(
+          "ADMIN_TOKENS must be distinct from TURBO_TOKENS, the token of {} is also a turbo token",
+          admin.name
+        ));
+      }
+    }
+    if self.max_artifact_size == 0 {
+      errors.push("MAX_ARTIFACT_SIZE must be greater than 0".to_string());
+    }
+    if self.memory_max_size == 0 {
+      errors.push("MEMORY_MAX_SIZE must be greater than 0".to_string());
+    }
+    let levels = zstd::compression_level_range();
+    if self.compression == Compression::Zstd && !levels.contains(&self.compression_level) {
+      errors.push(format!(
+        "COMPRESSION_LEVEL must be between {} and {}",
+        levels.start(),
+        levels.end()
+      ));
+    }
+    if self.presigned_url_expiry.is_zero() || self.presigned_url_expiry > MAX_PRESIGNED_URL_EXPIRY {
+      errors.push("PRESIGNED_URL_EXPIRY must be between 1s and 7d".to_string());
+    }
+    if self.gc_interval.is_zero() {
+      errors.push("GC_INTERVAL must be greater than 0".to_string());
+    }
+    if self.cache_tier == Some(CacheTier::File) && self.gc_max_size.is_none() {
+      errors.push("CACHE_TIER=file requires GC_MAX_SIZE to bound the local tier".to_string());
+    }
+    if self.retention_interval.is_zero() {
+      errors.push("RETENTION_INTERVAL must be greater than 0".to_string());
+    }
+    if let Some(Err(e)) = self.encryption_key_file.as_deref().map(Keyring::from_file) {
+      errors.push(format!("ENCRYPTION_KEY_FILE: {}", e));
+    }
   }
 
-  pub fn with_turbo_tokens(mut self, turbo_tokens: Vec<String>) -> Self {
-    self.turbo_tokens = turbo_tokens;
+  pub fn with_admin_tokens(mut self, admin_tokens: Vec<AdminToken>) -> Self {
+    self.admin_tokens = admin_tokens;
+    self
+  }
+
+  pub fn with_turbo_tokens<T: Into<TurboToken>>(mut self, turbo_tokens: Vec<T>) -> Self {
+    self.turbo_tokens = turbo_tokens.into_iter().map(Into::into).collect();
     self
   }
 
@@ -86,36 +702,476 @@ impl Config {
     self.bucket_name = bucket_name;
     self
   }
+
+  pub fn with_max_artifact_size(mut self, max_artifact_size: usize) -> Self {
+    self.max_artifact_size = max_artifact_size;
+    self
+  }
+
+  pub fn with_verified_size(mut self, verified_size: usize) -> Self {
+    self.verified_size = verified_size;
+    self
+  }
+
+  pub fn with_memory_max_size(mut self, memory_max_size: usize) -> Self {
+    self.memory_max_size = memory_max_size;
+    self
+  }
+
+  pub fn with_gc_max_age(mut self, gc_max_age: Option<Duration>) -> Self {
+    self.gc_max_age = gc_max_age;
+    self
+  }
+
+  pub fn with_gc_max_size(mut self, gc_max_size: Option<u64>) -> Self {
+    self.gc_max_size = gc_max_size;
+    self
+  }
+
+  pub fn with_cache_tier(mut self, cache_tier: Option<CacheTier>) -> Self {
+    self.cache_tier = cache_tier;
+    self
+  }
+
+  pub fn with_cache_write_policy(mut self, cache_write_policy: WritePolicy) -> Self {
+    self.cache_write_policy = cache_write_policy;
+    self
+  }
+
+  pub fn with_compression(mut self, compression: Compression) -> Self {
+    self.compression = compression;
+    self
+  }
+
+  pub fn with_encryption_key_file(mut self, encryption_key_file: Option<String>) -> Self {
+    self.encryption_key_file = encryption_key_file;
+    self
+  }
+
+  pub fn with_presigned_urls(mut self, presigned_urls: bool) -> Self {
+    self.presigned_urls = presigned_urls;
+    self
+  }
+
+  pub fn with_quotas(mut self, quotas: Vec<TeamQuota>, evict: bool) -> Self {
+    self.quotas = quotas;
+    self.quota_evict = evict;
+    self
+  }
+
+  pub fn with_retention_rules(mut self, retention_rules: Vec<RetentionRule>) -> Self {
+    self.retention_rules = retention_rules;
+    self
+  }
+
+  pub fn with_events_file(mut self, events_file: Option<String>) -> Self {
+    self.events_file = events_file;
+    self
+  }
 }
 
-pub fn get_fs_cache_path() -> String {
-  std::env::var("FS_PATH")
-    .or_else(|_| match std::env::temp_dir().to_str() {
-      Some(dir) => Ok(dir.to_string()),
-      None => Err(VarError::NotPresent),
-    })
-    .unwrap()
+// Config file values layered under environment variables. Problems are
+// recorded instead of returned so they can all be reported at once.
+struct Sources<'a> {
+  table: toml::Table,
+  path: &'a str,
+  env: &'a dyn Fn(&str) -> Option<String>,
+  // settings read so far, anything else in the config file is a typo
+  known: Vec<(&'static str, &'static str)>,
+  errors: Vec<String>,
+}
+
+impl Sources<'_> {
+  // Returns the raw value of a setting along with where it comes from, either
+  // the environment variable `var` or `key` in the `section` of the config file.
+  fn raw(
+    &mut self,
+    section: &'static str,
+    key: &'static str,
+    var: &'static str,
+  ) -> Option<(String, String)> {
+    self.known.push((section, key));
+    // an empty variable is unset, e.g. `VAR=` in an env file doesn't clear the config file
+    if let Some(value) = (self.env)(var).filter(|value| !value.is_empty()) {
+      return Some((var.to_string(), value));
+    }
+    let source = format!("{}.{} in {}", section, key, self.path);
+    let value = match self.table.get(section)?.get(key)? {
+      toml::Value::String(value) => value.clone(),
+      toml::Value::Integer(value) => value.to_string(),
+      toml::Value::Boolean(value) => value.to_string(),
+      // lists are joined the way they are written in environment variables
+      toml::Value::Array(values) => {
+        let values: Option<Vec<&str>> = values.iter().map(toml::Value::as_str).collect();
+        match values {
+          Some(values) => values.join(","),
+          None => {
+            self
+              .errors
+              .push(format!("{} must be a list of strings", source));
+            return None;
+          }
+        }
+      }
+      _ => {
+        self
+          .errors
+          .push(format!("{} must be a string, number or boolean", source));
+        return None;
+      }
+    };
+    Some((source, value))
+  }
+
+  fn parse<T>(
+    &mut self,
+    section: &'static str,
+    key: &'static str,
+    var: &'static str,
+    parse: impl FnOnce(&str) -> Result<T, String>,
+  ) -> Option<T> {
+    let (source, value) = self.raw(section, key, var)?;
+    parse(value.trim())
+      .map_err(|e| self.errors.push(format!("{}: {}", source, e)))
+      .ok()
+  }
+
+  fn required<T>(
+    &mut self,
+    section: &'static str,
+    key: &'static str,
+    var: &'static str,
+    parse: impl FnOnce(&str) -> Result<T, String>,
+  ) -> Option<T> {
+    if (self.env)(var).is_none() && self.table.get(section).and_then(|s| s.get(key)).is_none() {
+      self.known.push((section, key));
+      self.errors.push(format!(
+        "{} is required, set {} or {}.{}",
+        var, var, section, key
+      ));
+      return None;
+    }
+    self.parse(section, key, var, parse)
+  }
+
+  fn check_unknown(&mut self) {
+    for (section, values) in &self.table {
+      let Some(values) = values.as_table() else {
+        self.errors.push(format!(
+          "{} in {} must be a section, e.g. [{}]",
+          section, self.path, section
+        ));
+        continue;
+      };
+      for key in values.keys() {
+        if !self.known.contains(&(section.as_str(), key.as_str())) {
+          self.errors.push(format!(
+            "Unknown setting {}.{} in {}",
+            section, key, self.path
+          ));
+        }
+      }
+    }
+  }
+}
+
+fn string(s: &str) -> Result<String, String> {
+  Ok(s.to_string())
 }
 
-pub fn get_bucket_name() -> String {
-  std::env::var("BUCKET_NAME").unwrap_or("cache".to_string())
+fn number<T: FromStr>(s: &str) -> Result<T, String> {
+  s.parse().map_err(|_| format!("{} isn't a valid number", s))
 }
 
-pub fn get_port() -> u16 {
-  std::env::var("PORT")
-    .unwrap_or("4000".to_string())
+fn boolean(s: &str) -> Result<bool, String> {
+  match s {
+    "true" => Ok(true),
+    "false" => Ok(false),
+    _ => Err(format!("{} isn't true or false", s)),
+  }
+}
+
+/// Parses a duration such as `90d`, `12h`, `30m` or `45s`, plain numbers are seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+  let s = s.trim();
+  let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+    Some(index) => s.split_at(index),
+    None => (s, "s"),
+  };
+  let value: u64 = value
     .parse()
-    .expect("PORT must be a number")
+    .map_err(|_| format!("Invalid duration {}", s))?;
+  let seconds = match unit {
+    "s" => 1,
+    "m" => 60,
+    "h" => 3600,
+    "d" => 86400,
+    _ => {
+      return Err(format!(
+        "Invalid duration unit in {}, expected s, m, h or d",
+        s
+      ))
+    }
+  };
+  let seconds = value
+    .checked_mul(seconds)
+    .ok_or_else(|| format!("Duration {} is too large", s))?;
+  Ok(Duration::from_secs(seconds))
 }
 
-pub fn get_turbo_tokens() -> Vec<String> {
-  let tokens_str = std::env::var("TURBO_TOKENS").expect("TURBO_TOKENS is not set.");
-  tokens_str.split(',').map(|s| s.to_string()).collect()
+// Parses a comma separated list, empty entries are ignored.
+fn parse_list<T: FromStr<Err = String>>(s: &str) -> Result<Vec<T>, String> {
+  s.split(',')
+    .map(str::trim)
+    .filter(|s| !s.is_empty())
+    .map(str::parse)
+    .collect()
 }
 
-pub fn get_storage_provider() -> StorageProvider {
-  std::env::var("STORAGE_PROVIDER")
-    .unwrap_or("memory".to_string())
-    .as_str()
-    .into()
+// Parses a comma separated list of turbo tokens, see [`TurboToken::from_str`].
+fn parse_turbo_tokens(s: &str) -> Result<Vec<TurboToken>, String> {
+  let tokens: Vec<TurboToken> = parse_list(s)?;
+  if tokens.is_empty() {
+    return Err("At least one turbo token is required".to_string());
+  }
+  Ok(tokens)
+}
+
+#[cfg(test)]
+mod config_tests {
+  use std::collections::HashMap;
+
+  use super::*;
+  use crate::auth::hash_token;
+
+  #[test]
+  fn test_parse_turbo_token() {
+    let token: TurboToken = "secret".parse().unwrap();
+    assert_eq!(token, TurboToken::from("secret".to_string()));
+
+    let token: TurboToken = "secret:team-a|team-b:read".parse().unwrap();
+    assert_eq!(
+      token.teams,
+      Some(vec!["team-a".to_string(), "team-b".to_string()])
+    );
+    assert_eq!(token.permissions, vec![Permission::Read]);
+
+    let token: TurboToken = "secret:*:write".parse().unwrap();
+    assert_eq!(token.teams, None);
+    assert_eq!(token.permissions, vec![Permission::Write]);
+  }
+
+  #[test]
+  fn test_parse_duration() {
+    assert_eq!(parse_duration("45"), Ok(Duration::from_secs(45)));
+    assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+    assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(604800)));
+    assert!(parse_duration("7w").is_err());
+    assert!(parse_duration("d").is_err());
+  }
+
+  #[test]
+  fn test_file_cache_tier_is_bounded() {
+    let vars = [("TURBO_TOKENS", "token"), ("CACHE_TIER", "file")];
+    let errors = Config::from_sources(None, &env(&vars)).unwrap_err().0;
+    assert_eq!(
+      errors,
+      vec!["CACHE_TIER=file requires GC_MAX_SIZE to bound the local tier"]
+    );
+    let vars = [
+      ("TURBO_TOKENS", "token"),
+      ("CACHE_TIER", "file"),
+      ("GC_MAX_SIZE", "1073741824"),
+    ];
+    assert!(Config::from_sources(None, &env(&vars)).is_ok());
+  }
+
+  #[test]
+  fn test_parse_duration_too_large() {
+    assert_eq!(
+      parse_duration("999999999999999999d"),
+      Err("Duration 999999999999999999d is too large".to_string())
+    );
+    assert!(parse_duration("99999999999999999999999s").is_err());
+    let env = env(&[
+      ("TURBO_TOKENS", "token"),
+      ("GC_MAX_AGE", "999999999999999999d"),
+    ]);
+    let errors = Config::from_sources(None, &env).unwrap_err().0;
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("too large"), "{}", errors[0]);
+  }
+
+  fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+      .iter()
+      .map(|(var, value)| (var.to_string(), value.to_string()))
+      .collect();
+    move |var| vars.get(var).cloned()
+  }
+
+  #[test]
+  fn test_load_config_file() {
+    let file = r#"
+      [server]
+      port = 8080
+
+      [auth]
+      turbo_tokens = ["ci", "laptop:team-a:read"]
+
+      [storage]
+      provider = "file"
+      compression = "zstd"
+      presigned_url_expiry = "10m"
+
+      [limits]
+      max_artifact_size = 1024
+      verified_size = 4096
+
+      [logging]
+      level = "debug"
+    "#;
+    // empty variables don't override the file
+    let vars = env(&[("PORT", ""), ("COMPRESSION", "")]);
+    let config = Config::from_sources(Some(("cache.toml", file)), &vars).unwrap();
+    assert_eq!(config.port, 8080);
+    assert_eq!(config.turbo_tokens.len(), 2);
+    assert_eq!(config.turbo_tokens[1].permissions, vec![Permission::Read]);
+    assert!(matches!(config.storage_provider, StorageProvider::File));
+    assert_eq!(config.compression, Compression::Zstd);
+    assert_eq!(config.presigned_url_expiry, Duration::from_secs(600));
+    assert_eq!(config.max_artifact_size, 1024);
+    assert_eq!(config.verified_size, 4096);
+    assert_eq!(config.log_level, "debug");
+    assert_eq!(config.bucket_name, "cache");
+
+    // environment variables win over the file
+    let vars = env(&[("PORT", "9090"), ("TURBO_TOKENS", "other")]);
+    let config = Config::from_sources(Some(("cache.toml", file)), &vars).unwrap();
+    assert_eq!(config.port, 9090);
+    assert_eq!(
+      config.turbo_tokens,
+      vec![TurboToken::from("other".to_string())]
+    );
+    assert_eq!(config.max_artifact_size, 1024);
+
+    let example = include_str!("../config.example.toml");
+    assert!(Config::from_sources(Some(("config.example.toml", example)), &env(&[])).is_ok());
+  }
+
+  #[test]
+  fn test_config_errors_are_collected() {
+    let file = r#"
+      [storage]
+      provider = "ftp"
+      bucket = "typo"
+
+      [limits]
+      max_artifact_size = 0
+    "#;
+    let vars = env(&[
+      ("PORT", "http"),
+      ("GC_DRY_RUN", "yes"),
+      ("ENCRYPTION_KEY_FILE", "missing-keys.json"),
+    ]);
+    let ConfigError(errors) = Config::from_sources(Some(("cache.toml", file)), &vars).unwrap_err();
+    assert_eq!(
+      errors,
+      vec![
+        "PORT: http isn't a valid number",
+        "TURBO_TOKENS is required, set TURBO_TOKENS or auth.turbo_tokens",
+        "storage.provider in cache.toml: Invalid storage provider ftp, expected s3, file, gcs, azure or memory",
+        "GC_DRY_RUN: yes isn't true or false",
+        "Unknown setting storage.bucket in cache.toml",
+        "MAX_ARTIFACT_SIZE must be greater than 0",
+        "ENCRYPTION_KEY_FILE: invalid key file missing-keys.json: No such file or directory (os error 2)",
+      ]
+    );
+
+    let result = Config::from_sources(Some(("cache.toml", "[server")), &env(&[]));
+    assert!(result.unwrap_err().0[0].starts_with("Invalid config file cache.toml"));
+  }
+
+  #[test]
+  fn test_parse_team_quota() {
+    let quota: TeamQuota = "team-a:1024:10".parse().unwrap();
+    assert_eq!(
+      quota,
+      TeamQuota {
+        team: Some("team-a".to_string()),
+        max_bytes: Some(1024),
+        max_objects: Some(10),
+      }
+    );
+    let quota: TeamQuota = "*::10".parse().unwrap();
+    assert_eq!((quota.team, quota.max_bytes), (None, None));
+    for quota in [
+      "team-a",
+      "team-a::",
+      ":1024",
+      "team-a:1k",
+      "team-a:1:2:3",
+      "team/a:1024",
+    ] {
+      assert!(quota.parse::<TeamQuota>().is_err(), "{}", quota);
+    }
+  }
+
+  #[test]
+  fn test_admin_tokens_are_distinct() {
+    let admin = "ops:ci-token:read".parse::<AdminToken>().unwrap();
+    assert_eq!(admin.name, "ops");
+    assert_eq!(admin.permissions, vec![Permission::Read]);
+    assert!("ops".parse::<AdminToken>().is_err());
+    assert!("ops:token:admin".parse::<AdminToken>().is_err());
+
+    let env = |var: &str| match var {
+      "TURBO_TOKENS" => Some(hash_token("ci-token")),
+      "ADMIN_TOKENS" => Some("ops:ci-token,viewer:viewer-token:read".to_string()),
+      _ => None,
+    };
+    let errors = Config::from_sources(None, &env).unwrap_err().0;
+    assert_eq!(
+      errors,
+      vec![
+        "ADMIN_TOKENS must be distinct from TURBO_TOKENS, the token of ops is also a turbo token"
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_retention_rule() {
+    let rule: RetentionRule = "pr-*:7d::100".parse().unwrap();
+    assert_eq!(rule.max_age, Some(Duration::from_secs(7 * 86400)));
+    assert_eq!((rule.max_size, rule.keep_last), (None, Some(100)));
+    assert!(rule.matches("pr-123"));
+    assert!(!rule.matches("release-1"));
+
+    let rule: RetentionRule = "release:90d".parse().unwrap();
+    assert!(rule.matches("release"));
+    assert!(!rule.matches("release-1"));
+    let rule: RetentionRule = "*-web-*:1d".parse().unwrap();
+    assert!(rule.matches("team-web-pr"));
+    assert!(!rule.matches("team-api-pr"));
+    assert!("*:30d".parse::<RetentionRule>().unwrap().matches("any"));
+
+    for rule in [
+      "pr-*",
+      "pr-*:::",
+      ":7d",
+      "pr-*:7w",
+      "pr-*:7d:1:2:3",
+      "pr/*:7d",
+      "pr-?:7d",
+    ] {
+      assert!(rule.parse::<RetentionRule>().is_err(), "{}", rule);
+    }
+  }
+
+  #[test]
+  fn test_parse_invalid_turbo_token() {
+    for token in [":team", "secret:", "secret:*:admin", "secret:*:read:extra"] {
+      assert!(token.parse::<TurboToken>().is_err(), "{}", token);
+    }
+  }
 }
@@ -0,0 +1,141 @@
+use std::fmt::Display;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use log::{error, warn};
+
+use crate::helpers::{
+  bad_request, forbidden, insufficient_storage, internal_server_error, not_found,
+  payload_too_large, range_not_satisfiable, service_unavailable, unauthorized,
+};
+
+#[derive(Debug)]
+pub enum Error {
+  /// The requested artifact doesn't exist.
+  NotFound(String),
+  /// The request is missing valid credentials.
+  Unauthorized(String),
+  /// The credentials are valid but don't grant access to the resource.
+  Forbidden(String),
+  /// The request is malformed (bad params, headers or body).
+  Invalid(String),
+  /// The uploaded artifact is over the configured size limit.
+  PayloadTooLarge(String),
+  /// The team is over its storage quota.
+  QuotaExceeded(String),
+  /// The requested range is outside of the artifact, which has the given length.
+  RangeNotSatisfiable(u64),
+  /// The stored artifact failed verification (e.g. it was tampered with), it is
+  /// reported as missing so clients rebuild it.
+  Corrupted(String),
+  /// The storage backend failed, the client may retry the request.
+  StorageUnavailable(object_store::Error),
+  /// The server is misconfigured or hit an unexpected state.
+  Internal(String),
+}
+
+impl Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Error::NotFound(message)
+      | Error::Unauthorized(message)
+      | Error::Forbidden(message)
+      | Error::Invalid(message)
+      | Error::PayloadTooLarge(message)
+      | Error::QuotaExceeded(message)
+      | Error::Corrupted(message)
+      | Error::Internal(message) => write!(f, "{}", message),
+      Error::RangeNotSatisfiable(length) => {
+        write!(f, "Range not satisfiable for {} bytes", length)
+      }
+      Error::StorageUnavailable(e) => write!(f, "Storage unavailable: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::StorageUnavailable(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<object_store::Error> for Error {
+  fn from(e: object_store::Error) -> Self {
+    match e {
+      object_store::Error::NotFound { .. } => Error::NotFound("Artifact not found".to_string()),
+      e => Error::StorageUnavailable(e),
+    }
+  }
+}
+
+impl ResponseError for Error {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      Error::NotFound(_) | Error::Corrupted(_) => StatusCode::NOT_FOUND,
+      Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+      Error::Forbidden(_) => StatusCode::FORBIDDEN,
+      Error::Invalid(_) => StatusCode::BAD_REQUEST,
+      Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+      Error::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+      Error::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+      Error::StorageUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+      Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      Error::NotFound(message) => not_found(message.clone()),
+      Error::Unauthorized(message) => unauthorized(message.clone()),
+      Error::Forbidden(message) => forbidden(message.clone()),
+      Error::Invalid(message) => bad_request(message.clone()),
+      Error::PayloadTooLarge(message) => payload_too_large(message.clone()),
+      Error::QuotaExceeded(message) => insufficient_storage(message.clone()),
+      Error::RangeNotSatisfiable(length) => range_not_satisfiable(self.to_string(), *length),
+      Error::Corrupted(message) => {
+        warn!("Serving a corrupted artifact as a miss: {}", message);
+        not_found("Artifact not found".to_string())
+      }
+      Error::StorageUnavailable(e) => {
+        error!("Storage unavailable: {}", e);
+        service_unavailable("Storage backend is unavailable, please retry".to_string())
+      }
+      Error::Internal(message) => {
+        error!("{}", message);
+        internal_server_error(message.clone())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod error_tests {
+  use super::*;
+
+  #[test]
+  fn test_storage_errors_are_retryable() {
+    let e = Error::from(object_store::Error::Generic {
+      store: "S3",
+      source: "connection reset".into(),
+    });
+    assert_eq!(e.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+  }
+
+  #[test]
+  fn test_not_found_from_storage() {
+    let e = Error::from(object_store::Error::NotFound {
+      path: "team/123".to_string(),
+      source: "missing".into(),
+    });
+    assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+    assert_eq!(e.to_string(), "Artifact not found");
+  }
+
+  #[test]
+  fn test_corrupted_is_a_miss() {
+    let e = Error::Corrupted("team/123 failed authentication".to_string());
+    assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+  }
+}
@@ -0,0 +1,270 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  fs::{File, OpenOptions},
+  io::{BufWriter, Write},
+  sync::Mutex,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, error::Error};
+
+// Maximum number of sessions kept per team, the oldest ones are dropped first.
+const MAX_SESSIONS_PER_TEAM: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum EventSource {
+  Local,
+  Remote,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum EventType {
+  Hit,
+  Miss,
+}
+
+/// A cache event sent by turbo to `POST /v8/artifacts/events`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtifactEvent {
+  pub session_id: String,
+  pub source: EventSource,
+  pub event: EventType,
+  pub hash: String,
+  /// Time in milliseconds saved by a hit.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub duration: Option<u64>,
+}
+
+impl ArtifactEvent {
+  pub fn validate(&self) -> Result<(), Error> {
+    if self.session_id.trim().is_empty() {
+      return Err(Error::Invalid("event sessionId can't be empty".to_string()));
+    }
+    if self.hash.trim().is_empty() {
+      return Err(Error::Invalid("event hash can't be empty".to_string()));
+    }
+    Ok(())
+  }
+}
+
+/// Hit and miss counters along with the compute time saved by the hits.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventStats {
+  pub local_hits: u64,
+  pub local_misses: u64,
+  pub remote_hits: u64,
+  pub remote_misses: u64,
+  pub time_saved_ms: u64,
+}
+
+impl EventStats {
+  fn add(&mut self, event: &ArtifactEvent) {
+    match (event.source, event.event) {
+      (EventSource::Local, EventType::Hit) => self.local_hits += 1,
+      (EventSource::Local, EventType::Miss) => self.local_misses += 1,
+      (EventSource::Remote, EventType::Hit) => self.remote_hits += 1,
+      (EventSource::Remote, EventType::Miss) => self.remote_misses += 1,
+    }
+    if event.event == EventType::Hit {
+      self.time_saved_ms += event.duration.unwrap_or_default();
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TeamStats {
+  pub totals: EventStats,
+  pub sessions: HashMap<String, EventStats>,
+  // insertion order of `sessions`, used to drop the oldest ones
+  #[serde(skip)]
+  session_order: VecDeque<String>,
+}
+
+impl TeamStats {
+  fn add(&mut self, event: &ArtifactEvent) {
+    self.totals.add(event);
+    if !self.sessions.contains_key(&event.session_id) {
+      if self.session_order.len() >= MAX_SESSIONS_PER_TEAM {
+        if let Some(oldest) = self.session_order.pop_front() {
+          self.sessions.remove(&oldest);
+        }
+      }
+      self.session_order.push_back(event.session_id.clone());
+    }
+    self
+      .sessions
+      .entry(event.session_id.clone())
+      .or_default()
+      .add(event);
+  }
+}
+
+/// Destination of validated artifact events.
+pub trait EventSink: Send + Sync {
+  fn record(&self, team_id: &str, events: &[ArtifactEvent]) -> Result<(), Error>;
+}
+
+/// Aggregates events into per team and per session counters.
+#[derive(Default)]
+pub struct MemoryEventSink {
+  teams: Mutex<HashMap<String, TeamStats>>,
+}
+
+impl MemoryEventSink {
+  pub fn team_stats(&self, team_id: &str) -> TeamStats {
+    let teams = self.teams.lock().unwrap_or_else(|e| e.into_inner());
+    teams.get(team_id).cloned().unwrap_or_default()
+  }
+}
+
+impl EventSink for MemoryEventSink {
+  fn record(&self, team_id: &str, events: &[ArtifactEvent]) -> Result<(), Error> {
+    let mut teams = self.teams.lock().unwrap_or_else(|e| e.into_inner());
+    let stats = teams.entry(team_id.to_string()).or_default();
+    for event in events {
+      stats.add(event);
+    }
+    Ok(())
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EventRecord<'a> {
+  timestamp: u64,
+  team_id: &'a str,
+  #[serde(flatten)]
+  event: &'a ArtifactEvent,
+}
+
+/// Appends every event as a JSON line to a file, e.g. to be shipped to a log pipeline.
+pub struct JsonlEventSink {
+  file: Mutex<BufWriter<File>>,
+}
+
+impl JsonlEventSink {
+  pub fn new(path: &str) -> Result<Self, Error> {
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .map_err(|e| Error::Internal(format!("error opening events file {}: {}", path, e)))?;
+    Ok(JsonlEventSink {
+      file: Mutex::new(BufWriter::new(file)),
+    })
+  }
+}
+
+impl EventSink for JsonlEventSink {
+  fn record(&self, team_id: &str, events: &[ArtifactEvent]) -> Result<(), Error> {
+    let timestamp = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_millis() as u64)
+      .unwrap_or_default();
+    let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+    let write_error = |e: std::io::Error| Error::Internal(format!("error writing events: {}", e));
+    for event in events {
+      let record = EventRecord {
+        timestamp,
+        team_id,
+        event,
+      };
+      serde_json::to_writer(&mut *file, &record)
+        .map_err(|e| Error::Internal(format!("error serializing event: {}", e)))?;
+      file.write_all(b"\n").map_err(write_error)?;
+    }
+    file.flush().map_err(write_error)
+  }
+}
+
+/// Records events into the in-memory aggregates and any additional configured sink.
+#[derive(Default)]
+pub struct EventStore {
+  stats: MemoryEventSink,
+  sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl EventStore {
+  pub fn new(config: &Config) -> Result<Self, Error> {
+    let mut sinks: Vec<Box<dyn EventSink>> = vec![];
+    if let Some(path) = &config.events_file {
+      debug!("Appending artifact events to {}", path);
+      sinks.push(Box::new(JsonlEventSink::new(path)?));
+    }
+    Ok(EventStore {
+      stats: MemoryEventSink::default(),
+      sinks,
+    })
+  }
+
+  pub fn record(&self, team_id: &str, events: &[ArtifactEvent]) -> Result<(), Error> {
+    for event in events {
+      event.validate()?;
+    }
+    self.stats.record(team_id, events)?;
+    for sink in &self.sinks {
+      sink.record(team_id, events)?;
+    }
+    Ok(())
+  }
+
+  pub fn team_stats(&self, team_id: &str) -> TeamStats {
+    self.stats.team_stats(team_id)
+  }
+}
+
+#[cfg(test)]
+mod events_tests {
+  use super::*;
+
+  #[test]
+  fn test_jsonl_event_sink() {
+    let path = std::env::temp_dir().join("turbo-remote-cache-events-test.jsonl");
+    let _ = std::fs::remove_file(&path);
+    let sink = JsonlEventSink::new(path.to_str().unwrap()).unwrap();
+    let event = ArtifactEvent {
+      session_id: "s1".to_string(),
+      source: EventSource::Remote,
+      event: EventType::Hit,
+      hash: "123".to_string(),
+      duration: Some(100),
+    };
+    sink.record("team", &[event.clone(), event]).unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<serde_json::Value> = content
+      .lines()
+      .map(|line| serde_json::from_str(line).unwrap())
+      .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["teamId"], "team");
+    assert_eq!(lines[0]["event"], "HIT");
+    assert_eq!(lines[0]["source"], "REMOTE");
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn test_sessions_are_bounded() {
+    let sink = MemoryEventSink::default();
+    let events: Vec<ArtifactEvent> = (0..MAX_SESSIONS_PER_TEAM + 10)
+      .map(|i| ArtifactEvent {
+        session_id: i.to_string(),
+        source: EventSource::Local,
+        event: EventType::Miss,
+        hash: "123".to_string(),
+        duration: None,
+      })
+      .collect();
+    sink.record("team", &events).unwrap();
+    let stats = sink.team_stats("team");
+    assert_eq!(stats.sessions.len(), MAX_SESSIONS_PER_TEAM);
+    assert!(!stats.sessions.contains_key("0"));
+    assert_eq!(stats.totals.local_misses, events.len() as u64);
+  }
+}
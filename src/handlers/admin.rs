@@ -0,0 +1,426 @@
+use actix_web::{
+  web::{delete, get, scope, Data, Path, Query, ReqData, ServiceConfig},
+  HttpResponse,
+};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use log::info;
+use object_store::ObjectMeta;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  auth::{AdminAuth, AdminPrincipal},
+  config::Permission,
+  error::Error,
+  helpers::{get_artifact_path, validate_artifact_id, validate_team_id},
+  storage::{ArtifactMetadata, StorageStore},
+};
+
+// Artifacts listed per page when the request doesn't set a limit.
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+// Maximum number of teams an artifact is deleted from at the same time.
+const MAX_CONCURRENT_DELETES: usize = 16;
+
+#[derive(Deserialize)]
+struct ListArtifactsQuery {
+  limit: Option<usize>,
+  /// Hash to list after, the `next` of the previous page.
+  after: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArtifactInfo {
+  hash: String,
+  /// Size of the artifact as sent to clients.
+  size: u64,
+  /// Size of the stored object, smaller when it is compressed.
+  stored_size: u64,
+  last_modified: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  task_duration_ms: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  tag: Option<String>,
+}
+
+impl ArtifactInfo {
+  fn new(meta: &ObjectMeta, metadata: &ArtifactMetadata) -> Self {
+    ArtifactInfo {
+      hash: meta.location.filename().unwrap_or_default().to_string(),
+      size: metadata.content_length(meta),
+      stored_size: meta.size as u64,
+      last_modified: meta.last_modified.to_rfc3339(),
+      task_duration_ms: metadata.duration,
+      tag: metadata.tag.clone(),
+    }
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ArtifactDetails {
+  #[serde(flatten)]
+  info: ArtifactInfo,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  sha256: Option<String>,
+  compressed: bool,
+  encrypted: bool,
+}
+
+#[derive(Serialize)]
+struct ListArtifactsResponse {
+  artifacts: Vec<ArtifactInfo>,
+  next: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeletedArtifact {
+  team: String,
+  hash: String,
+  /// Size of the stored object.
+  size: u64,
+}
+
+impl From<ObjectMeta> for DeletedArtifact {
+  fn from(meta: ObjectMeta) -> Self {
+    let mut parts = meta.location.parts();
+    let team = parts.next().map(|part| part.as_ref().to_string());
+    DeletedArtifact {
+      team: team.unwrap_or_default(),
+      hash: meta.location.filename().unwrap_or_default().to_string(),
+      size: meta.size as u64,
+    }
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeleteResponse {
+  deleted: Vec<DeletedArtifact>,
+  reclaimed_bytes: u64,
+}
+
+impl DeleteResponse {
+  fn new(deleted: Vec<ObjectMeta>) -> Self {
+    let deleted: Vec<DeletedArtifact> = deleted.into_iter().map(DeletedArtifact::from).collect();
+    let reclaimed_bytes = deleted.iter().map(|artifact| artifact.size).sum();
+    DeleteResponse {
+      deleted,
+      reclaimed_bytes,
+    }
+  }
+}
+
+fn team_id_or_400(path: Path<String>) -> Result<String, Error> {
+  let team_id = path.into_inner();
+  validate_team_id(&team_id)?;
+  Ok(team_id)
+}
+
+// Lists the artifacts of a team a page at a time, ordered by hash.
+async fn list_artifacts(
+  path: Path<String>,
+  query: Query<ListArtifactsQuery>,
+  principal: ReqData<AdminPrincipal>,
+  storage: Data<StorageStore>,
+) -> Result<HttpResponse, Error> {
+  let team_id = team_id_or_400(path)?;
+  principal.authorize(Permission::Read)?;
+  let ListArtifactsQuery { limit, after } = query.into_inner();
+  let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+  if limit == 0 || limit > MAX_PAGE_SIZE {
+    return Err(Error::Invalid(format!(
+      "limit must be between 1 and {}",
+      MAX_PAGE_SIZE
+    )));
+  }
+  if let Some(after) = &after {
+    validate_artifact_id(after)?;
+  }
+  let page = storage
+    .list_artifacts(&team_id, after.as_deref(), limit)
+    .await?;
+  info!(
+    "Listed {} artifacts of team {}",
+    page.artifacts.len(),
+    team_id
+  );
+  let artifacts = page
+    .artifacts
+    .iter()
+    .map(|(meta, metadata)| ArtifactInfo::new(meta, metadata))
+    .collect();
+  Ok(HttpResponse::Ok().json(ListArtifactsResponse {
+    artifacts,
+    next: page.next,
+  }))
+}
+
+async fn get_artifact(
+  path: Path<(String, String)>,
+  principal: ReqData<AdminPrincipal>,
+  storage: Data<StorageStore>,
+) -> Result<HttpResponse, Error> {
+  let (team_id, id) = path.into_inner();
+  validate_team_id(&team_id)?;
+  validate_artifact_id(&id)?;
+  principal.authorize(Permission::Read)?;
+  let (meta, metadata) = storage.head(&get_artifact_path(&id, &team_id)).await?;
+  Ok(HttpResponse::Ok().json(ArtifactDetails {
+    info: ArtifactInfo::new(&meta, &metadata),
+    sha256: metadata.sha256.clone(),
+    compressed: metadata.encoding.is_some(),
+    encrypted: metadata.encryption.is_some(),
+  }))
+}
+
+// Deleting is idempotent, artifacts already gone are left out of the response.
+async fn delete_artifact(
+  path: Path<(String, String)>,
+  principal: ReqData<AdminPrincipal>,
+  storage: Data<StorageStore>,
+) -> Result<HttpResponse, Error> {
+  let (team_id, id) = path.into_inner();
+  validate_team_id(&team_id)?;
+  validate_artifact_id(&id)?;
+  principal.authorize(Permission::Write)?;
+  let deleted = storage.delete(&get_artifact_path(&id, &team_id)).await?;
+  match &deleted {
+    Some(_) => info!("Deleted artifact {} of team {}", id, team_id),
+    None => info!("Artifact {} of team {} was already deleted", id, team_id),
+  }
+  Ok(HttpResponse::Ok().json(DeleteResponse::new(deleted.into_iter().collect())))
+}
+
+// Deletes the artifact with the given hash from every team, e.g. a poisoned build.
+async fn delete_hash(
+  path: Path<String>,
+  principal: ReqData<AdminPrincipal>,
+  storage: Data<StorageStore>,
+) -> Result<HttpResponse, Error> {
+  let id = path.into_inner();
+  validate_artifact_id(&id)?;
+  principal.authorize(Permission::Write)?;
+  let teams = storage.teams().await?;
+  let storage = &storage;
+  let deleted: Vec<Option<ObjectMeta>> = stream::iter(teams)
+    .map(|team_id| {
+      let path = get_artifact_path(&id, &team_id);
+      async move { storage.delete(&path).await }
+    })
+    .buffer_unordered(MAX_CONCURRENT_DELETES)
+    .try_collect()
+    .await?;
+  let response = DeleteResponse::new(deleted.into_iter().flatten().collect());
+  info!(
+    "Deleted artifact {} from {} teams",
+    id,
+    response.deleted.len()
+  );
+  Ok(HttpResponse::Ok().json(response))
+}
+
+async fn purge_team(
+  path: Path<String>,
+  principal: ReqData<AdminPrincipal>,
+  storage: Data<StorageStore>,
+) -> Result<HttpResponse, Error> {
+  let team_id = team_id_or_400(path)?;
+  principal.authorize(Permission::Write)?;
+  let response = DeleteResponse::new(storage.delete_prefix(&team_id).await?);
+  info!(
+    "Purged {} artifacts ({} bytes) of team {}",
+    response.deleted.len(),
+    response.reclaimed_bytes,
+    team_id
+  );
+  Ok(HttpResponse::Ok().json(response))
+}
+
+pub fn configure(storage: Data<StorageStore>) -> impl FnOnce(&mut ServiceConfig) {
+  let c = move |cfg: &mut ServiceConfig| {
+    cfg.service(
+      scope("/admin")
+        .wrap(AdminAuth)
+        .app_data(storage)
+        .route("/artifacts/{id}", delete().to(delete_hash))
+        .route("/teams/{team}", delete().to(purge_team))
+        .route("/teams/{team}/artifacts", get().to(list_artifacts))
+        .route("/teams/{team}/artifacts/{id}", get().to(get_artifact))
+        .route("/teams/{team}/artifacts/{id}", delete().to(delete_artifact)),
+    );
+  };
+  c
+}
+
+#[cfg(test)]
+mod admin_tests {
+  use std::sync::Arc;
+
+  use actix_web::{http::header::AUTHORIZATION, test, web::Bytes, App};
+  use serde_json::{json, Value};
+
+  use super::*;
+  use crate::config::{AdminToken, Config};
+
+  fn config() -> Arc<Config> {
+    let admin_tokens = vec![
+      "ops:admin-token".parse::<AdminToken>().unwrap(),
+      "viewer:viewer-token:read".parse::<AdminToken>().unwrap(),
+    ];
+    let config = Config::default()
+      .with_turbo_tokens(vec!["turbo-token".to_string()])
+      .with_admin_tokens(admin_tokens);
+    Arc::new(config)
+  }
+
+  async fn put(storage: &StorageStore, path: &str, duration: u64) {
+    let body = futures_util::stream::iter(vec![Ok(Bytes::from_static(b"data"))]);
+    let metadata = ArtifactMetadata {
+      duration: Some(duration),
+      tag: Some("tag".to_string()),
+      ..Default::default()
+    };
+    storage.put_stream(path, body, &metadata).await.unwrap();
+  }
+
+  #[actix_web::test]
+  async fn test_admin_list_artifacts() {
+    let config = config();
+    let storage = Data::new(StorageStore::new(&config).unwrap());
+    put(&storage, "team/01", 10).await;
+    put(&storage, "team/02", 20).await;
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage)),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+      .uri("/admin/teams/team/artifacts?limit=1")
+      .insert_header((AUTHORIZATION, "Bearer admin-token"))
+      .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["next"], "01");
+    let artifact = &body["artifacts"][0];
+    assert_eq!(artifact["hash"], "01");
+    assert_eq!(artifact["size"], 4);
+    assert_eq!(artifact["taskDurationMs"], 10);
+    assert_eq!(artifact["tag"], "tag");
+
+    let req = test::TestRequest::get()
+      .uri("/admin/teams/team/artifacts?limit=1&after=01")
+      .insert_header((AUTHORIZATION, "Bearer admin-token"))
+      .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["artifacts"][0]["hash"], "02");
+    assert_eq!(body["next"], json!(null));
+
+    let req = test::TestRequest::get()
+      .uri("/admin/teams/team/artifacts/02")
+      .insert_header((AUTHORIZATION, "Bearer admin-token"))
+      .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["taskDurationMs"], 20);
+    assert_eq!(body["compressed"], false);
+    assert!(body["sha256"].is_string());
+
+    for (uri, status) in [
+      ("/admin/teams/team/artifacts/03", 404),
+      ("/admin/teams/team/artifacts?limit=0", 400),
+      ("/admin/teams/team/artifacts?after=..", 400),
+      ("/admin/teams/te.am/artifacts", 400),
+    ] {
+      let req = test::TestRequest::get()
+        .uri(uri)
+        .insert_header((AUTHORIZATION, "Bearer admin-token"))
+        .to_request();
+      assert_eq!(
+        test::call_service(&app, req).await.status(),
+        status,
+        "{}",
+        uri
+      );
+    }
+    let req = test::TestRequest::get()
+      .uri("/admin/teams/team/artifacts")
+      .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+    // turbo tokens never reach the admin API
+    let req = test::TestRequest::get()
+      .uri("/admin/teams/team/artifacts")
+      .insert_header((AUTHORIZATION, "Bearer turbo-token"))
+      .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+  }
+
+  fn delete_request(uri: &str) -> test::TestRequest {
+    test::TestRequest::delete()
+      .uri(uri)
+      .insert_header((AUTHORIZATION, "Bearer admin-token"))
+  }
+
+  #[actix_web::test]
+  async fn test_admin_delete_artifacts() {
+    let config = config();
+    let storage = Data::new(StorageStore::new(&config).unwrap());
+    for path in ["team-a/01", "team-a/02", "team-b/01", "team-b/03"] {
+      put(&storage, path, 10).await;
+    }
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage.clone())),
+    )
+    .await;
+
+    let body: Value = test::call_and_read_body_json(
+      &app,
+      delete_request("/admin/teams/team-b/artifacts/03").to_request(),
+    )
+    .await;
+    assert_eq!(
+      body,
+      json!({"deleted": [{"team": "team-b", "hash": "03", "size": 4}], "reclaimedBytes": 4})
+    );
+    let body: Value = test::call_and_read_body_json(
+      &app,
+      delete_request("/admin/teams/team-b/artifacts/03").to_request(),
+    )
+    .await;
+    assert_eq!(body, json!({"deleted": [], "reclaimedBytes": 0}));
+    assert!(!storage.exists("team-b/03.meta").await.unwrap());
+
+    // a read only admin token can't delete, nor can a turbo token writing to every team
+    for (token, status) in [("viewer-token", 403), ("turbo-token", 401)] {
+      for uri in ["/admin/artifacts/01", "/admin/teams/team-a"] {
+        let req = test::TestRequest::delete()
+          .uri(uri)
+          .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+          .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status);
+      }
+    }
+    assert!(storage.exists("team-a/01").await.unwrap());
+
+    let body: Value =
+      test::call_and_read_body_json(&app, delete_request("/admin/artifacts/01").to_request()).await;
+    assert_eq!(body["deleted"].as_array().unwrap().len(), 2);
+    assert_eq!(body["reclaimedBytes"], 8);
+    assert!(!storage.exists("team-a/01").await.unwrap());
+    assert!(!storage.exists("team-b/01").await.unwrap());
+
+    let body: Value =
+      test::call_and_read_body_json(&app, delete_request("/admin/teams/team-a").to_request()).await;
+    assert_eq!(
+      body,
+      json!({"deleted": [{"team": "team-a", "hash": "02", "size": 4}], "reclaimedBytes": 4})
+    );
+    assert!(!storage.exists("team-a/02.meta").await.unwrap());
+    let body: Value =
+      test::call_and_read_body_json(&app, delete_request("/admin/teams/team-a").to_request()).await;
+    assert_eq!(body, json!({"deleted": [], "reclaimedBytes": 0}));
+  }
+}
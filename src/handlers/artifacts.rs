@@ -1,17 +1,32 @@
+use std::{collections::HashMap, sync::Arc};
+
 use crate::{
-  auth::Auth,
-  config::Config,
+  auth::{Auth, Principal},
+  config::{Config, Permission},
+  error::Error,
+  events::{ArtifactEvent, EventStore, TeamStats},
   helpers::{
-    artifact_params_or_400, exists_cached_artifact, get_artifact_path, not_found, GetArtifactQuery,
+    artifact_metadata_or_400, artifact_params_or_400, artifact_range_or_416, get_artifact_path,
+    insert_accept_ranges, insert_artifact_metadata, team_id_or_400, validate_artifact_id,
+    ArtifactValidators, GetArtifactQuery,
   },
+  metrics::Metrics,
   storage::StorageStore,
 };
 use actix_web::{
-  web::{get, head, post, put, resource, scope, Bytes, Data, Path, Query, ServiceConfig},
-  HttpResponse, Responder,
+  body::SizedStream,
+  error::PayloadError,
+  http::header::{ContentRange, ContentRangeSpec, CONTENT_LENGTH, LOCATION, RANGE},
+  web::Bytes,
+  web::{
+    get, head, post, put, resource, scope, Data, Json, JsonConfig, Path, Payload, Query, ReqData,
+    ServiceConfig,
+  },
+  HttpRequest, HttpResponse, Responder,
 };
-use log::info;
-use serde::Serialize;
+use futures_util::{stream, StreamExt};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
 
 #[derive(Serialize)]
 pub struct Status {
@@ -23,11 +38,83 @@ struct PutArtifactResponse {
   urls: Vec<String>,
 }
 
-async fn post_artifacts_events() -> impl Responder {
-  info!("Artifacts events received");
-  HttpResponse::Ok()
-    .content_type("application/json")
-    .body("{}")
+#[derive(Serialize)]
+struct ArtifactEventsResponse {
+  #[serde(rename = "teamId")]
+  team_id: String,
+  #[serde(flatten)]
+  stats: TeamStats,
+}
+
+#[derive(Deserialize)]
+struct ArtifactQueryRequest {
+  hashes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ArtifactQueryError {
+  message: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ArtifactQueryResult {
+  Found {
+    size: u64,
+    #[serde(rename = "taskDurationMs")]
+    task_duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+  },
+  Error {
+    error: ArtifactQueryError,
+  },
+}
+
+// Maximum number of storage lookups running at the same time for a single query.
+const MAX_CONCURRENT_QUERIES: usize = 16;
+
+fn redirect(url: String) -> HttpResponse {
+  HttpResponse::TemporaryRedirect()
+    .insert_header((LOCATION, url))
+    .finish()
+}
+
+async fn post_artifacts_events(
+  query: Query<GetArtifactQuery>,
+  principal: ReqData<Principal>,
+  body: Json<Vec<ArtifactEvent>>,
+  events: Data<EventStore>,
+) -> Result<HttpResponse, Error> {
+  let team_id = team_id_or_400(query)?;
+  principal.authorize(&team_id, Permission::Read)?;
+  let body = body.into_inner();
+  events.record(&team_id, &body)?;
+  info!(
+    "{} artifacts events received for team {}",
+    body.len(),
+    team_id
+  );
+  Ok(
+    HttpResponse::Ok()
+      .content_type("application/json")
+      .body("{}"),
+  )
+}
+
+async fn get_artifacts_events(
+  query: Query<GetArtifactQuery>,
+  principal: ReqData<Principal>,
+  events: Data<EventStore>,
+) -> Result<HttpResponse, Error> {
+  let team_id = team_id_or_400(query)?;
+  principal.authorize(&team_id, Permission::Read)?;
+  let stats = events.team_stats(&team_id);
+  Ok(
+    HttpResponse::Ok()
+      .content_type("application/json")
+      .json(ArtifactEventsResponse { team_id, stats }),
+  )
 }
 
 async fn get_status() -> impl Responder {
@@ -40,86 +127,219 @@ async fn get_status() -> impl Responder {
     .json(obj)
 }
 
-async fn head_artifact(
-  path: Path<String>,
+// Answers with the metadata of every requested hash, `null` for the ones that aren't cached.
+async fn query_artifacts(
   query: Query<GetArtifactQuery>,
+  principal: ReqData<Principal>,
+  body: Json<ArtifactQueryRequest>,
   storage: Data<StorageStore>,
-) -> impl Responder {
-  let (id, team_id) = match artifact_params_or_400(path, query) {
-    Ok((id, team_id)) => (id, team_id),
-    Err(e) => return e,
-  };
-
-  if exists_cached_artifact(&id, &team_id, &storage)
-    .await
-    .is_ok()
-  {
-    info!("Artifact {} exists", id);
+) -> Result<HttpResponse, Error> {
+  let team_id = team_id_or_400(query)?;
+  principal.authorize(&team_id, Permission::Read)?;
+  let ArtifactQueryRequest { hashes } = body.into_inner();
+  for hash in &hashes {
+    validate_artifact_id(hash)?;
+  }
+  let results: HashMap<String, Option<ArtifactQueryResult>> = stream::iter(hashes)
+    .map(|hash| {
+      let path = get_artifact_path(&hash, &team_id);
+      let storage = &storage;
+      async move {
+        let result = match storage.head(&path).await {
+          Ok((meta, metadata)) => Some(ArtifactQueryResult::Found {
+            size: metadata.content_length(&meta),
+            task_duration_ms: metadata.duration.unwrap_or_default(),
+            tag: metadata.tag,
+          }),
+          Err(Error::NotFound(_)) => None,
+          Err(e) => {
+            error!("Failed to query artifact {}: {}", path, e);
+            Some(ArtifactQueryResult::Error {
+              error: ArtifactQueryError {
+                message: "Failed to query artifact".to_string(),
+              },
+            })
+          }
+        };
+        (hash, result)
+      }
+    })
+    .buffer_unordered(MAX_CONCURRENT_QUERIES)
+    .collect()
+    .await;
+  info!("Queried {} artifacts of team {}", results.len(), team_id);
+  Ok(
     HttpResponse::Ok()
       .content_type("application/json")
-      .body("true")
-  } else {
-    not_found("Artifact not found".to_string())
+      .json(results),
+  )
+}
+
+async fn head_artifact(
+  req: HttpRequest,
+  path: Path<String>,
+  query: Query<GetArtifactQuery>,
+  principal: ReqData<Principal>,
+  storage: Data<StorageStore>,
+) -> Result<HttpResponse, Error> {
+  let (id, team_id) = artifact_params_or_400(path, query)?;
+  principal.authorize(&team_id, Permission::Read)?;
+  let path = get_artifact_path(&id, &team_id);
+  let (meta, metadata) = storage.head(&path).await?;
+  let validators = ArtifactValidators::new(&meta, &metadata);
+  if validators.not_modified(&req) {
+    return Ok(validators.not_modified_response());
   }
+  info!("Artifact {} exists", id);
+  let mut response = HttpResponse::Ok();
+  insert_artifact_metadata(&mut response, &metadata);
+  validators.insert(&mut response);
+  insert_accept_ranges(&mut response, &metadata);
+  // no body is sent for HEAD requests, its size is only used as Content-Length
+  let body = SizedStream::new(
+    metadata.content_length(&meta),
+    stream::empty::<Result<Bytes, Error>>(),
+  );
+  Ok(response.content_type("application/octet-stream").body(body))
 }
+
 async fn get_artifact(
+  req: HttpRequest,
   path: Path<String>,
   query: Query<GetArtifactQuery>,
+  principal: ReqData<Principal>,
   storage: Data<StorageStore>,
-) -> impl Responder {
-  let (id, team_id) = match artifact_params_or_400(path, query) {
-    Ok((id, team_id)) => (id, team_id),
-    Err(e) => return e,
-  };
-  if exists_cached_artifact(&id, &team_id, &storage)
-    .await
-    .is_ok()
-  {
-    let path: String = get_artifact_path(&id, &team_id);
-    let data = storage.get(&path).await.unwrap();
-    info!("Artifact {} retrieved from {}", id, path);
-    HttpResponse::Ok()
-      .content_type("application/octet-stream")
-      .body(data)
-  } else {
-    not_found("Artifact not found".to_string())
+) -> Result<HttpResponse, Error> {
+  let (id, team_id) = artifact_params_or_400(path, query)?;
+  principal.authorize(&team_id, Permission::Read)?;
+  let path: String = get_artifact_path(&id, &team_id);
+  if let Some(url) = storage.download_url(&path).await? {
+    info!(
+      "Artifact {} download redirected to the storage provider",
+      id
+    );
+    return Ok(redirect(url));
   }
+  // check the validators and range first to not open the artifact for nothing
+  if ArtifactValidators::requested(&req) || req.headers().contains_key(RANGE) {
+    let (meta, metadata) = storage.head(&path).await?;
+    let validators = ArtifactValidators::new(&meta, &metadata);
+    if validators.not_modified(&req) {
+      return Ok(validators.not_modified_response());
+    }
+    if let Some(range) = artifact_range_or_416(&req, &meta, &metadata, &validators)? {
+      let length = metadata.content_length(&meta);
+      let body = storage.get_range(&path, range.clone()).await?;
+      info!(
+        "Artifact {} bytes {}-{} retrieved from {}",
+        id,
+        range.start,
+        range.end - 1,
+        path
+      );
+      let mut response = HttpResponse::PartialContent();
+      insert_artifact_metadata(&mut response, &metadata);
+      validators.insert(&mut response);
+      insert_accept_ranges(&mut response, &metadata);
+      return Ok(
+        response
+          .insert_header(ContentRange(ContentRangeSpec::Bytes {
+            range: Some((range.start, range.end - 1)),
+            instance_length: Some(length),
+          }))
+          .content_type("application/octet-stream")
+          .body(SizedStream::new(body.size, body.stream)),
+      );
+    }
+  }
+  let (body, metadata) = storage.get(&path).await?;
+  info!("Artifact {} retrieved from {}", id, path);
+  let mut response = HttpResponse::Ok();
+  insert_artifact_metadata(&mut response, &metadata);
+  ArtifactValidators::new(&body.meta, &metadata).insert(&mut response);
+  insert_accept_ranges(&mut response, &metadata);
+  Ok(
+    response
+      .content_type("application/octet-stream")
+      .body(SizedStream::new(body.size, body.stream)),
+  )
 }
 
 async fn put_artifact(
+  req: HttpRequest,
   path: Path<String>,
   query: Query<GetArtifactQuery>,
-  body: Bytes,
+  principal: ReqData<Principal>,
+  payload: Payload,
   storage: Data<StorageStore>,
-) -> impl Responder {
-  let (id, team_id) = match artifact_params_or_400(path, query) {
-    Ok((id, team_id)) => (id, team_id),
-    Err(e) => return e,
-  };
-  // store artifact
+  config: Data<Arc<Config>>,
+) -> Result<HttpResponse, Error> {
+  let (id, team_id) = artifact_params_or_400(path, query)?;
+  principal.authorize(&team_id, Permission::Write)?;
+  let metadata = artifact_metadata_or_400(&req)?;
+  let max_size = config.max_artifact_size;
+  let too_large =
+    || Error::PayloadTooLarge(format!("Artifact exceeds the {} bytes limit", max_size));
+  let content_length = req
+    .headers()
+    .get(CONTENT_LENGTH)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.parse::<usize>().ok());
+  if content_length.is_some_and(|length| length > max_size) {
+    return Err(too_large());
+  }
   let path = get_artifact_path(&id, &team_id);
-  let _ = storage.put(&path, body).await;
+  storage
+    .check_quota(&path, content_length.map(|length| length as u64))
+    .await?;
+  // stream the artifact into storage, aborting as soon as it grows over the limit,
+  // uploads aren't redirected to presigned URLs since those can't enforce it
+  let mut size = 0;
+  let body = payload.map(move |chunk| {
+    let chunk = chunk.map_err(|e| match e {
+      PayloadError::Overflow => too_large(),
+      e => Error::Invalid(format!("Failed to read artifact: {}", e)),
+    })?;
+    size += chunk.len();
+    if size > max_size {
+      return Err(too_large());
+    }
+    Ok(chunk)
+  });
+  storage.put_stream(&path, body, &metadata).await?;
   info!("Artifact {} stored in {}", id, path);
-  HttpResponse::Ok()
-    .content_type("application/json")
-    .json(PutArtifactResponse { urls: vec![path] })
+  Ok(
+    HttpResponse::Ok()
+      .content_type("application/json")
+      .json(PutArtifactResponse { urls: vec![path] }),
+  )
 }
 
-pub fn configure(config: &Config) -> impl FnOnce(&mut ServiceConfig) + '_ {
-  let c = |cfg: &mut ServiceConfig| {
+pub fn configure(
+  storage: Data<StorageStore>,
+  events: Data<EventStore>,
+) -> impl FnOnce(&mut ServiceConfig) {
+  let c = move |cfg: &mut ServiceConfig| {
     cfg.service(
       scope("/v8/artifacts")
+        .wrap(Metrics)
         .route("/status", get().to(get_status))
         .service(
           scope("")
             .wrap(Auth)
+            .app_data(storage)
+            .app_data(events)
+            .app_data(
+              JsonConfig::default().error_handler(|e, _| Error::Invalid(e.to_string()).into()),
+            )
+            .route("", post().to(query_artifacts))
             .route("/events", post().to(post_artifacts_events))
+            .route("/events", get().to(get_artifacts_events))
             .service(
               resource("/{id}")
                 .route(get().to(get_artifact))
                 .route(head().to(head_artifact))
-                .route(put().to(put_artifact))
-                .app_data(Data::new(StorageStore::new(config))),
+                .route(put().to(put_artifact)),
             ),
         ),
     );
@@ -133,19 +353,58 @@ mod artifacts_tests {
   use std::sync::Arc;
 
   use super::*;
-  use crate::config::{Config, StorageProvider};
+  use crate::config::{Compression, Config, StorageProvider, TurboToken};
+  use crate::metrics::CORRUPTED_ARTIFACTS;
   use actix_web::{
-    http::{header::ContentType, Method},
-    test, App,
+    body::{BodySize, MessageBody},
+    http::{
+      header::{ContentType, HeaderValue},
+      Method,
+    },
+    test,
+    web::Bytes,
+    App,
   };
 
+  fn storage(config: &Config) -> Data<StorageStore> {
+    Data::new(StorageStore::new(config).unwrap())
+  }
+
+  fn events() -> Data<EventStore> {
+    Data::new(EventStore::default())
+  }
+
+  // Overwrites a stored object with `data` as if it had been corrupted a while after
+  // being written, recent artifacts aren't quarantined as they may be overwritten.
+  fn corrupt(path: &std::path::Path, data: &[u8]) {
+    std::fs::write(path, data).unwrap();
+    let modified = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    std::fs::File::options()
+      .write(true)
+      .open(path)
+      .unwrap()
+      .set_times(std::fs::FileTimes::new().set_modified(modified))
+      .unwrap();
+  }
+
+  // Waits for a corrupted artifact to be moved to `path`, which happens in the background.
+  async fn quarantined(path: &std::path::Path) -> bool {
+    for _ in 0..100 {
+      if path.exists() {
+        return true;
+      }
+      actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    false
+  }
+
   #[actix_web::test]
   async fn test_get_status() {
     let config = Arc::new(Config::default());
     let app = test::init_service(
       App::new()
         .app_data(Data::new(config.clone()))
-        .configure(configure(&config)),
+        .configure(configure(storage(&config), events())),
     )
     .await;
     let req = test::TestRequest::get()
@@ -164,7 +423,7 @@ mod artifacts_tests {
     let app = test::init_service(
       App::new()
         .app_data(Data::new(config.clone()))
-        .configure(configure(&config)),
+        .configure(configure(storage(&config), events())),
     )
     .await;
     let req = test::TestRequest::default()
@@ -180,13 +439,63 @@ mod artifacts_tests {
     );
   }
 
+  #[actix_web::test]
+  async fn test_artifacts_malformed_authorization() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let headers = [
+      HeaderValue::from_static("test"),
+      HeaderValue::from_static("Bearer"),
+      HeaderValue::from_static("Bearer    "),
+      HeaderValue::from_static("Basic dGVzdA=="),
+      HeaderValue::from_static("Bearertest"),
+      HeaderValue::from_static("Bearer tes"),
+      HeaderValue::from_static("Bearer test2"),
+      HeaderValue::from_bytes(b"Bearer t\xc3\xa9st").unwrap(),
+    ];
+    for header in headers {
+      let req = test::TestRequest::default()
+        .method(Method::HEAD)
+        .uri("/v8/artifacts/123?teamId=test")
+        .insert_header(("Authorization", header.clone()))
+        .to_request();
+      let resp = test::call_service(&app, req).await;
+      assert_eq!(resp.status(), 401, "{:?}", header);
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_authorization_scheme_case_insensitive() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    for header in ["bearer test", "BEARER test", "  Bearer   test  "] {
+      let req = test::TestRequest::default()
+        .method(Method::HEAD)
+        .uri("/v8/artifacts/123?teamId=test")
+        .insert_header(("Authorization", header))
+        .to_request();
+      let resp = test::call_service(&app, req).await;
+      assert_eq!(resp.status(), 404, "{}", header);
+    }
+  }
+
   #[actix_web::test]
   async fn test_artifacts_authorized() {
     let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
     let app = test::init_service(
       App::new()
         .app_data(Data::new(config.clone()))
-        .configure(configure(&config)),
+        .configure(configure(storage(&config), events())),
     )
     .await;
     let req = test::TestRequest::default()
@@ -209,7 +518,7 @@ mod artifacts_tests {
     let app = test::init_service(
       App::new()
         .app_data(Data::new(config.clone()))
-        .configure(configure(&config)),
+        .configure(configure(storage(&config), events())),
     )
     .await;
     let req = test::TestRequest::default()
@@ -228,7 +537,7 @@ mod artifacts_tests {
     let app = test::init_service(
       App::new()
         .app_data(Data::new(config.clone()))
-        .configure(configure(&config)),
+        .configure(configure(storage(&config), events())),
     )
     .await;
     let put_req = test::TestRequest::default()
@@ -258,7 +567,7 @@ mod artifacts_tests {
     let app = test::init_service(
       App::new()
         .app_data(Data::new(config.clone()))
-        .configure(configure(&config)),
+        .configure(configure(storage(&config), events())),
     )
     .await;
     let put_req = test::TestRequest::default()
@@ -290,7 +599,7 @@ mod artifacts_tests {
     let app = test::init_service(
       App::new()
         .app_data(Data::new(config.clone()))
-        .configure(configure(&config)),
+        .configure(configure(storage(&config), events())),
     )
     .await;
     let put_req = test::TestRequest::default()
@@ -306,6 +615,40 @@ mod artifacts_tests {
     assert_eq!(str::from_utf8(&body).unwrap(), r#"{"urls":["test/123"]}"#);
   }
 
+  #[actix_web::test]
+  async fn test_artifacts_presigned_urls_fallback() {
+    let config = Arc::new(
+      Config::default()
+        .with_turbo_tokens(vec!["test".to_string()])
+        .with_presigned_urls(true),
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    // the memory provider can't presign URLs, artifacts are proxied
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"test"))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+
+    let get_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(get_resp.status(), 200);
+    let body = test::read_body(get_resp).await;
+    assert_eq!(str::from_utf8(&body).unwrap(), "test");
+  }
+
   #[actix_web::test]
   async fn test_artifacts_with_file_provider() {
     let config = Arc::new(
@@ -317,7 +660,7 @@ mod artifacts_tests {
     let app = test::init_service(
       App::new()
         .app_data(Data::new(config.clone()))
-        .configure(configure(&config)),
+        .configure(configure(storage(&config), events())),
     )
     .await;
     let put_req = test::TestRequest::default()
@@ -344,4 +687,779 @@ mod artifacts_tests {
     let body = test::read_body(get_resp).await;
     assert_eq!(str::from_utf8(&body).unwrap(), "test");
   }
+
+  #[actix_web::test]
+  async fn test_artifacts_put_too_large() {
+    let config = Arc::new(
+      Config::default()
+        .with_turbo_tokens(vec!["test".to_string()])
+        .with_max_artifact_size(4),
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"too large"))
+      .insert_header(ContentType::json())
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 413);
+
+    let head_req = test::TestRequest::default()
+      .method(Method::HEAD)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(ContentType::json())
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let head_resp = test::call_service(&app, head_req).await;
+    assert_eq!(head_resp.status(), 404);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_get_multipart() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    // larger than a single multipart chunk
+    let data = Bytes::from(vec![7u8; 12 * 1024 * 1024]);
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(data.clone())
+      .insert_header(ContentType::json())
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+
+    let get_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(ContentType::json())
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(get_resp.status(), 200);
+    assert_eq!(test::read_body(get_resp).await, data);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_compressed() {
+    let config = Arc::new(
+      Config::default()
+        .with_turbo_tokens(vec!["test".to_string()])
+        .with_compression(Compression::Zstd),
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let data = Bytes::from(b"turbo artifact ".repeat(100_000));
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(data.clone())
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+
+    let get_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(get_resp.status(), 200);
+    assert_eq!(
+      get_resp.response().body().size(),
+      BodySize::Sized(data.len() as u64)
+    );
+    assert_eq!(test::read_body(get_resp).await, data);
+
+    // stored bytes don't map to the artifact ones, ranges are ignored
+    let range_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(("Authorization", "Bearer test"))
+      .insert_header(("range", "bytes=0-9"))
+      .to_request();
+    let range_resp = test::call_service(&app, range_req).await;
+    assert_eq!(range_resp.status(), 200);
+    assert_eq!(range_resp.headers().get("accept-ranges").unwrap(), "none");
+    assert_eq!(test::read_body(range_resp).await, data);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_encrypted() {
+    let dir = std::env::temp_dir().join("turbo-remote-cache-encrypted");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let key_file = dir.join("keys.json");
+    std::fs::write(
+      &key_file,
+      r#"{"active": "k1", "keys": {"k1": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="}}"#,
+    )
+    .unwrap();
+    let config = Arc::new(
+      Config::default()
+        .with_turbo_tokens(vec!["test".to_string()])
+        .with_storage_provider(StorageProvider::File)
+        .with_fs_cache_path(dir.to_str().unwrap().to_string())
+        .with_encryption_key_file(Some(key_file.to_str().unwrap().to_string())),
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"secret artifact"))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+    let object = dir.join("cache/test/123");
+    let mut stored = std::fs::read(&object).unwrap();
+    assert!(!stored.windows(6).any(|window| window == b"secret"));
+
+    let get_req = || {
+      test::TestRequest::default()
+        .method(Method::GET)
+        .uri("/v8/artifacts/123?teamId=test")
+        .insert_header(("Authorization", "Bearer test"))
+        .to_request()
+    };
+    let get_resp = test::call_service(&app, get_req()).await;
+    assert_eq!(get_resp.status(), 200);
+    assert_eq!(test::read_body(get_resp).await, "secret artifact");
+
+    // a flipped ciphertext byte fails authentication
+    stored[0] ^= 1;
+    corrupt(&object, &stored);
+    let corrupted = CORRUPTED_ARTIFACTS.get();
+    let get_resp = test::call_service(&app, get_req()).await;
+    assert_eq!(get_resp.status(), 404);
+    assert!(quarantined(&dir.join("cache/.quarantine/test/123")).await);
+    assert!(CORRUPTED_ARTIFACTS.get() > corrupted);
+    assert!(!object.exists());
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_checksum_mismatch() {
+    let dir = std::env::temp_dir().join("turbo-remote-cache-checksum");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Arc::new(
+      Config::default()
+        .with_turbo_tokens(vec!["test".to_string()])
+        .with_storage_provider(StorageProvider::File)
+        .with_fs_cache_path(dir.to_str().unwrap().to_string()),
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"artifact"))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+    // truncated by a crash while writing
+    corrupt(&dir.join("cache/test/123"), b"artif");
+
+    let corrupted = CORRUPTED_ARTIFACTS.get();
+    let get_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(get_resp.status(), 404);
+    assert!(quarantined(&dir.join("cache/.quarantine/test/123")).await);
+    assert!(CORRUPTED_ARTIFACTS.get() > corrupted);
+    assert!(!dir.join("cache/test/123").exists());
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_invalid_params() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let long_id = "a".repeat(65);
+    let long_team = "t".repeat(129);
+    let uris = [
+      "/v8/artifacts/..?teamId=test".to_string(),
+      "/v8/artifacts/..%2F..%2Fetc%2Fpasswd?teamId=test".to_string(),
+      "/v8/artifacts/12%2F34?teamId=test".to_string(),
+      "/v8/artifacts/%2E%2E?teamId=test".to_string(),
+      "/v8/artifacts/123?teamId=..".to_string(),
+      "/v8/artifacts/123?teamId=..%2Fother".to_string(),
+      "/v8/artifacts/123?teamId=team%2F%2Fother".to_string(),
+      "/v8/artifacts/123?teamId=".to_string(),
+      "/v8/artifacts/123?slug=.quarantine".to_string(),
+      format!("/v8/artifacts/{}?teamId=test", long_id),
+      format!("/v8/artifacts/123?teamId={}", long_team),
+    ];
+    for uri in uris {
+      for method in [Method::GET, Method::HEAD, Method::PUT] {
+        let req = test::TestRequest::default()
+          .method(method.clone())
+          .uri(&uri)
+          .set_payload(Bytes::from_static(b"test"))
+          .insert_header(("Authorization", "Bearer test"))
+          .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{} {}", method, uri);
+      }
+    }
+
+    let req = test::TestRequest::default()
+      .method(Method::POST)
+      .uri("/v8/artifacts?teamId=test")
+      .set_json(serde_json::json!({ "hashes": ["123", "../456"] }))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_conditional_requests() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"test"))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+
+    let request = |method: Method, header: Option<(&str, &str)>| {
+      let mut req = test::TestRequest::default()
+        .method(method)
+        .uri("/v8/artifacts/123?teamId=test")
+        .insert_header(("Authorization", "Bearer test"));
+      if let Some(header) = header {
+        req = req.insert_header((header.0.to_string(), header.1.to_string()));
+      }
+      req.to_request()
+    };
+    let resp = test::call_service(&app, request(Method::HEAD, None)).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.response().body().size(), BodySize::Sized(4));
+    // sha256 of "test"
+    let etag = r#""9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08""#;
+    assert_eq!(resp.headers().get("etag").unwrap(), etag);
+    let last_modified = resp.headers().get("last-modified").unwrap().clone();
+    let last_modified = last_modified.to_str().unwrap();
+
+    for (header, status) in [
+      (("if-none-match", etag), 304),
+      (("if-none-match", "*"), 304),
+      (("if-none-match", r#""other", W/"other""#), 200),
+      (("if-modified-since", last_modified), 304),
+      (("if-modified-since", "Thu, 01 Jan 1970 00:00:00 GMT"), 200),
+    ] {
+      for method in [Method::GET, Method::HEAD] {
+        let resp = test::call_service(&app, request(method.clone(), Some(header))).await;
+        assert_eq!(resp.status(), status, "{} {:?}", method, header);
+        assert_eq!(resp.headers().get("etag").unwrap(), etag);
+      }
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_range_requests() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"0123456789"))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+
+    let head_req = test::TestRequest::default()
+      .method(Method::HEAD)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let head_resp = test::call_service(&app, head_req).await;
+    assert_eq!(head_resp.headers().get("accept-ranges").unwrap(), "bytes");
+
+    let cases = [
+      (
+        vec![("range", "bytes=2-5")],
+        206,
+        Some("bytes 2-5/10"),
+        "2345",
+      ),
+      (
+        vec![("range", "bytes=-3")],
+        206,
+        Some("bytes 7-9/10"),
+        "789",
+      ),
+      (
+        vec![("range", "bytes=8-100")],
+        206,
+        Some("bytes 8-9/10"),
+        "89",
+      ),
+      (vec![("range", "bytes=0-1,4-5")], 200, None, "0123456789"),
+      (vec![("range", "bytes=abc")], 200, None, "0123456789"),
+      (
+        vec![("range", "bytes=2-5"), ("if-range", r#""outdated""#)],
+        200,
+        None,
+        "0123456789",
+      ),
+    ];
+    for (headers, status, content_range, body) in cases {
+      let mut req = test::TestRequest::default()
+        .method(Method::GET)
+        .uri("/v8/artifacts/123?teamId=test")
+        .insert_header(("Authorization", "Bearer test"));
+      for header in &headers {
+        req = req.insert_header(*header);
+      }
+      let resp = test::call_service(&app, req.to_request()).await;
+      assert_eq!(resp.status(), status, "{:?}", headers);
+      assert_eq!(
+        resp
+          .headers()
+          .get("content-range")
+          .map(|value| value.to_str().unwrap()),
+        content_range
+      );
+      assert_eq!(test::read_body(resp).await, body);
+    }
+
+    let req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(("Authorization", "Bearer test"))
+      .insert_header(("range", "bytes=20-30"))
+      .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 416);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes */10");
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_metadata_headers() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"test"))
+      .insert_header(("Authorization", "Bearer test"))
+      .insert_header(("x-artifact-duration", "1500"))
+      .insert_header(("x-artifact-tag", "signature"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+
+    for method in [Method::GET, Method::HEAD] {
+      let req = test::TestRequest::default()
+        .method(method)
+        .uri("/v8/artifacts/123?teamId=test")
+        .insert_header(("Authorization", "Bearer test"))
+        .to_request();
+      let resp = test::call_service(&app, req).await;
+      assert_eq!(resp.status(), 200);
+      assert_eq!(resp.headers().get("x-artifact-duration").unwrap(), "1500");
+      assert_eq!(resp.headers().get("x-artifact-tag").unwrap(), "signature");
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_invalid_duration() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"test"))
+      .insert_header(("Authorization", "Bearer test"))
+      .insert_header(("x-artifact-duration", "soon"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 400);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_token_team_scope() {
+    let config = Arc::new(
+      Config::default()
+        .with_turbo_tokens(vec!["team-a-token:team-a".parse::<TurboToken>().unwrap()]),
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    for (team, status) in [("team-a", 200), ("team-b", 403)] {
+      let put_req = test::TestRequest::default()
+        .method(Method::PUT)
+        .uri(&format!("/v8/artifacts/123?teamId={}", team))
+        .set_payload(Bytes::from_static(b"test"))
+        .insert_header(("Authorization", "Bearer team-a-token"))
+        .to_request();
+      let put_resp = test::call_service(&app, put_req).await;
+      assert_eq!(put_resp.status(), status, "{}", team);
+    }
+    let get_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?slug=team-b")
+      .insert_header(("Authorization", "Bearer team-a-token"))
+      .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(get_resp.status(), 403);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_read_only_token() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec![
+      "ci-token".parse::<TurboToken>().unwrap(),
+      "dev-token:*:read".parse::<TurboToken>().unwrap(),
+    ]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    for (token, status) in [("dev-token", 403), ("ci-token", 200)] {
+      let put_req = test::TestRequest::default()
+        .method(Method::PUT)
+        .uri("/v8/artifacts/123?teamId=test")
+        .set_payload(Bytes::from_static(b"test"))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+      let put_resp = test::call_service(&app, put_req).await;
+      assert_eq!(put_resp.status(), status, "{}", token);
+    }
+    let get_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?teamId=test")
+      .insert_header(("Authorization", "Bearer dev-token"))
+      .to_request();
+    let get_resp = test::call_service(&app, get_req).await;
+    assert_eq!(get_resp.status(), 200);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_team_quota() {
+    let config = Arc::new(
+      Config::default()
+        .with_turbo_tokens(vec!["test".to_string()])
+        .with_quotas(vec!["team-a:6".parse().unwrap()], false),
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    for (team, id, payload, status) in [
+      ("team-a", "1", "aaaa", 200),
+      ("team-a", "2", "bbbb", 507),
+      ("team-a", "3", "cccccccc", 507),
+      ("team-b", "2", "bbbb", 200),
+    ] {
+      let put_req = test::TestRequest::default()
+        .method(Method::PUT)
+        .uri(&format!("/v8/artifacts/{}?teamId={}", id, team))
+        .set_payload(payload)
+        .insert_header(("Authorization", "Bearer test"))
+        .to_request();
+      let put_resp = test::call_service(&app, put_req).await;
+      assert_eq!(put_resp.status(), status, "{} {}", team, id);
+      if status == 507 {
+        let body = test::read_body(put_resp).await;
+        assert!(str::from_utf8(&body)
+          .unwrap()
+          .contains(r#""message":"Artifact exceeds the remaining quota of team team-a"#));
+      }
+    }
+    // an artifact larger than the whole quota can't fit even after evictions
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/3?teamId=team-a")
+      .insert_header((CONTENT_LENGTH, "8"))
+      .set_payload("cccccccc")
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 413);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_hashed_token() {
+    let hashed = format!("{}:team-a", crate::auth::hash_token("secret"));
+    let config =
+      Arc::new(Config::default().with_turbo_tokens(vec![hashed.parse::<TurboToken>().unwrap()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let hash = crate::auth::hash_token("secret");
+    for (token, status) in [("secret", 200), (hash.as_str(), 401), ("other", 401)] {
+      let put_req = test::TestRequest::default()
+        .method(Method::PUT)
+        .uri("/v8/artifacts/123?teamId=team-a")
+        .set_payload(Bytes::from_static(b"test"))
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+      let put_resp = test::call_service(&app, put_req).await;
+      assert_eq!(put_resp.status(), status, "{}", token);
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_query() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=test")
+      .set_payload(Bytes::from_static(b"test"))
+      .insert_header(("Authorization", "Bearer test"))
+      .insert_header(("x-artifact-duration", "42"))
+      .insert_header(("x-artifact-tag", "signature"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+
+    let query_req = test::TestRequest::default()
+      .method(Method::POST)
+      .uri("/v8/artifacts?teamId=test")
+      .set_json(serde_json::json!({ "hashes": ["123", "456"] }))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let query_resp = test::call_service(&app, query_req).await;
+    assert_eq!(query_resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(query_resp).await;
+    assert_eq!(
+      body,
+      serde_json::json!({
+        "123": { "size": 4, "taskDurationMs": 42, "tag": "signature" },
+        "456": null,
+      })
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_query_invalid_body() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let query_req = test::TestRequest::default()
+      .method(Method::POST)
+      .uri("/v8/artifacts?teamId=test")
+      .set_json(serde_json::json!({ "hashes": "123" }))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let query_resp = test::call_service(&app, query_req).await;
+    assert_eq!(query_resp.status(), 400);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_events() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let events_req = test::TestRequest::default()
+      .method(Method::POST)
+      .uri("/v8/artifacts/events?teamId=test")
+      .set_json(serde_json::json!([
+        { "sessionId": "s1", "source": "REMOTE", "event": "HIT", "hash": "123", "duration": 500 },
+        { "sessionId": "s1", "source": "LOCAL", "event": "MISS", "hash": "456" },
+        { "sessionId": "s2", "source": "LOCAL", "event": "HIT", "hash": "789", "duration": 250 },
+      ]))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let events_resp = test::call_service(&app, events_req).await;
+    assert_eq!(events_resp.status(), 200);
+
+    let stats_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/events?teamId=test")
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let stats_resp = test::call_service(&app, stats_req).await;
+    assert_eq!(stats_resp.status(), 200);
+    let body: serde_json::Value = test::read_body_json(stats_resp).await;
+    assert_eq!(body["teamId"], "test");
+    assert_eq!(
+      body["totals"],
+      serde_json::json!({
+        "localHits": 1,
+        "localMisses": 1,
+        "remoteHits": 1,
+        "remoteMisses": 0,
+        "timeSavedMs": 750,
+      })
+    );
+    assert_eq!(body["sessions"]["s1"]["timeSavedMs"], 500);
+    assert_eq!(body["sessions"]["s2"]["localHits"], 1);
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_invalid_events() {
+    let config = Arc::new(Config::default().with_turbo_tokens(vec!["test".to_string()]));
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let bodies = [
+      serde_json::json!([{ "sessionId": "s1", "source": "CLOUD", "event": "HIT", "hash": "1" }]),
+      serde_json::json!([{ "sessionId": "s1", "source": "LOCAL", "event": "SKIP", "hash": "1" }]),
+      serde_json::json!([{ "sessionId": "", "source": "LOCAL", "event": "HIT", "hash": "1" }]),
+      serde_json::json!([{ "sessionId": "s1", "source": "LOCAL", "event": "HIT" }]),
+    ];
+    for body in bodies {
+      let events_req = test::TestRequest::default()
+        .method(Method::POST)
+        .uri("/v8/artifacts/events?teamId=test")
+        .set_json(&body)
+        .insert_header(("Authorization", "Bearer test"))
+        .to_request();
+      let events_resp = test::call_service(&app, events_req).await;
+      assert_eq!(events_resp.status(), 400, "{}", body);
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_artifacts_metrics() {
+    let config = Arc::new(
+      Config::default()
+        .with_turbo_tokens(vec!["test".to_string()])
+        .with_admin_tokens(vec!["ops:admin-token".parse().unwrap()]),
+    );
+    let app = test::init_service(
+      App::new()
+        .app_data(Data::new(config.clone()))
+        .configure(crate::metrics::configure)
+        .configure(configure(storage(&config), events())),
+    )
+    .await;
+    let put_req = test::TestRequest::default()
+      .method(Method::PUT)
+      .uri("/v8/artifacts/123?teamId=metrics-team")
+      .set_payload(Bytes::from_static(b"test"))
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    let put_resp = test::call_service(&app, put_req).await;
+    assert_eq!(put_resp.status(), 200);
+    let unauthorized_req = test::TestRequest::default()
+      .method(Method::GET)
+      .uri("/v8/artifacts/123?teamId=metrics-team")
+      .insert_header(("Authorization", "Bearer nope"))
+      .to_request();
+    let unauthorized_resp = test::call_service(&app, unauthorized_req).await;
+    assert_eq!(unauthorized_resp.status(), 401);
+    // misses of made-up teams don't get a label of their own
+    let miss_req = test::TestRequest::default()
+      .method(Method::HEAD)
+      .uri("/v8/artifacts/123?teamId=metrics-made-up")
+      .insert_header(("Authorization", "Bearer test"))
+      .to_request();
+    assert_eq!(test::call_service(&app, miss_req).await.status(), 404);
+
+    // team names are only exposed to admin tokens
+    for token in [None, Some("test")] {
+      let mut req = test::TestRequest::get().uri("/metrics");
+      if let Some(token) = token {
+        req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+      }
+      assert_eq!(
+        test::call_service(&app, req.to_request()).await.status(),
+        401
+      );
+    }
+    let metrics_req = test::TestRequest::get()
+      .uri("/metrics")
+      .insert_header(("Authorization", "Bearer admin-token"))
+      .to_request();
+    let metrics_resp = test::call_service(&app, metrics_req).await;
+    assert_eq!(metrics_resp.status(), 200);
+    let body = test::read_body(metrics_resp).await;
+    let body = str::from_utf8(&body).unwrap();
+    assert!(body.contains(
+      r#"turbo_cache_requests_total{method="PUT",route="/v8/artifacts/{id}",status="200",team="metrics-team"} 1"#
+    ));
+    assert!(body.contains(r#"turbo_cache_bytes_in_total{team="metrics-team"} 4"#));
+    assert!(!body.contains("metrics-made-up"));
+    assert!(body.contains(r#"turbo_cache_auth_failures_total{reason="invalid_token"}"#));
+    assert!(body.contains(r#"turbo_cache_storage_operation_duration_seconds_count{operation="put_multipart",result="ok"}"#));
+  }
 }
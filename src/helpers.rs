@@ -1,11 +1,26 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use actix_web::{
-  web::{Data, Path, Query},
-  HttpResponse,
+  http::header::{
+    ByteRangeSpec, ContentRange, ContentRangeSpec, EntityTag, Header, HttpDate, IfModifiedSince,
+    IfNoneMatch, IfRange, Range, ACCEPT_RANGES, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE,
+    LAST_MODIFIED, RANGE,
+  },
+  web::{Path, Query},
+  HttpRequest, HttpResponse, HttpResponseBuilder,
 };
 use log::error;
+use object_store::ObjectMeta;
 use serde::{Deserialize, Serialize};
 
-use crate::storage::StorageStore;
+use crate::{error::Error, storage::ArtifactMetadata};
+
+pub const ARTIFACT_DURATION_HEADER: &str = "x-artifact-duration";
+pub const ARTIFACT_TAG_HEADER: &str = "x-artifact-tag";
+
+// Turbo hashes are 16 hex characters, leave room for longer digests such as SHA-256.
+const MAX_ARTIFACT_ID_LEN: usize = 64;
+const MAX_TEAM_ID_LEN: usize = 128;
 
 #[derive(Serialize)]
 pub struct BoomResponse {
@@ -36,6 +51,17 @@ pub fn unauthorized(message: String) -> HttpResponse {
     .json(value)
 }
 
+pub fn forbidden(message: String) -> HttpResponse {
+  let value = BoomResponse {
+    status_code: 403,
+    error: Some("Forbidden".to_string()),
+    message,
+  };
+  HttpResponse::Forbidden()
+    .content_type("application/json")
+    .json(value)
+}
+
 pub fn not_found(message: String) -> HttpResponse {
   error!("{}", message);
   let value = BoomResponse {
@@ -70,6 +96,17 @@ pub fn not_implemented(message: String) -> HttpResponse {
     .json(value)
 }
 
+pub fn service_unavailable(message: String) -> HttpResponse {
+  let value = BoomResponse {
+    status_code: 503,
+    error: Some("Service Unavailable".to_string()),
+    message,
+  };
+  HttpResponse::ServiceUnavailable()
+    .content_type("application/json")
+    .json(value)
+}
+
 pub fn precondition_failed(message: String) -> HttpResponse {
   let value = BoomResponse {
     status_code: 412,
@@ -81,6 +118,43 @@ pub fn precondition_failed(message: String) -> HttpResponse {
     .json(value)
 }
 
+pub fn payload_too_large(message: String) -> HttpResponse {
+  let value = BoomResponse {
+    status_code: 413,
+    error: Some("Payload Too Large".to_string()),
+    message,
+  };
+  HttpResponse::PayloadTooLarge()
+    .content_type("application/json")
+    .json(value)
+}
+
+pub fn insufficient_storage(message: String) -> HttpResponse {
+  let value = BoomResponse {
+    status_code: 507,
+    error: Some("Insufficient Storage".to_string()),
+    message,
+  };
+  HttpResponse::InsufficientStorage()
+    .content_type("application/json")
+    .json(value)
+}
+
+pub fn range_not_satisfiable(message: String, length: u64) -> HttpResponse {
+  let value = BoomResponse {
+    status_code: 416,
+    error: Some("Range Not Satisfiable".to_string()),
+    message,
+  };
+  HttpResponse::RangeNotSatisfiable()
+    .insert_header(ContentRange(ContentRangeSpec::Bytes {
+      range: None,
+      instance_length: Some(length),
+    }))
+    .content_type("application/json")
+    .json(value)
+}
+
 pub fn ok(message: String) -> HttpResponse {
   let value = BoomResponse {
     status_code: 200,
@@ -100,25 +174,65 @@ pub struct GetArtifactQuery {
   slug: Option<String>,
 }
 
+impl GetArtifactQuery {
+  /// The team of the request, turbo sends either `teamId`, `team` or `slug`.
+  pub fn team_id(self) -> Option<String> {
+    self.team_id.or(self.team).or(self.slug)
+  }
+}
+
+/// Checks `id` is a hex artifact hash, so it's safe to use in a storage path.
+pub fn validate_artifact_id(id: &str) -> Result<(), Error> {
+  if id.is_empty() || id.len() > MAX_ARTIFACT_ID_LEN {
+    return Err(Error::Invalid(format!(
+      "artifact hash must be between 1 and {} characters",
+      MAX_ARTIFACT_ID_LEN
+    )));
+  }
+  if !id.bytes().all(|c| c.is_ascii_hexdigit()) {
+    return Err(Error::Invalid(
+      "artifact hash must only contain hex characters".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+/// Checks `team_id` is a slug made of letters, digits, `-` and `_`, so it's safe to
+/// use in a storage path.
+pub fn validate_team_id(team_id: &str) -> Result<(), Error> {
+  if team_id.is_empty() || team_id.len() > MAX_TEAM_ID_LEN {
+    return Err(Error::Invalid(format!(
+      "team must be between 1 and {} characters",
+      MAX_TEAM_ID_LEN
+    )));
+  }
+  if !team_id
+    .bytes()
+    .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+  {
+    return Err(Error::Invalid(
+      "team must only contain letters, digits, - and _".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+pub fn team_id_or_400(query: Query<GetArtifactQuery>) -> Result<String, Error> {
+  let team_id = query
+    .into_inner()
+    .team_id()
+    .ok_or_else(|| Error::Invalid("team is required in query parameters".to_string()))?;
+  validate_team_id(&team_id)?;
+  Ok(team_id)
+}
+
 pub fn artifact_params_or_400(
   path: Path<String>,
   query: Query<GetArtifactQuery>,
-) -> Result<(String, String), HttpResponse> {
+) -> Result<(String, String), Error> {
   let id = path.into_inner();
-  let GetArtifactQuery {
-    team_id,
-    slug,
-    team,
-  } = query.into_inner();
-  let team_id = team_id.or(team).or(slug);
-  let team_id = match team_id {
-    Some(team_id) => team_id,
-    None => {
-      return Err(bad_request(
-        "team is required in query parameters".to_string(),
-      ));
-    }
-  };
+  validate_artifact_id(&id)?;
+  let team_id = team_id_or_400(query)?;
   Ok((id, team_id))
 }
 
@@ -126,14 +240,164 @@ pub fn get_artifact_path(artifact_id: &String, team_id: &String) -> String {
   format!("{}/{}", team_id, artifact_id)
 }
 
-pub async fn exists_cached_artifact(
-  artifact_id: &String,
-  team_id: &String,
-  storage: &Data<StorageStore>,
-) -> Result<bool, String> {
-  let artifact_path = get_artifact_path(artifact_id, team_id);
-  if !storage.exists(&artifact_path).await {
-    return Err(format!("Artifact {} doesn't exist.", artifact_path));
+pub fn artifact_metadata_or_400(req: &HttpRequest) -> Result<ArtifactMetadata, Error> {
+  let headers = req.headers();
+  let duration = match headers.get(ARTIFACT_DURATION_HEADER) {
+    Some(value) => match value.to_str().ok().and_then(|v| v.trim().parse().ok()) {
+      Some(duration) => Some(duration),
+      None => {
+        return Err(Error::Invalid(format!(
+          "{} must be a number of milliseconds",
+          ARTIFACT_DURATION_HEADER
+        )));
+      }
+    },
+    None => None,
+  };
+  let tag = match headers.get(ARTIFACT_TAG_HEADER) {
+    Some(value) => match value.to_str() {
+      Ok(tag) => Some(tag.to_string()),
+      Err(_) => {
+        return Err(Error::Invalid(format!(
+          "{} must be a valid header string",
+          ARTIFACT_TAG_HEADER
+        )));
+      }
+    },
+    None => None,
+  };
+  Ok(ArtifactMetadata {
+    duration,
+    tag,
+    ..Default::default()
+  })
+}
+
+pub fn insert_artifact_metadata(builder: &mut HttpResponseBuilder, metadata: &ArtifactMetadata) {
+  if let Some(duration) = metadata.duration {
+    builder.insert_header((ARTIFACT_DURATION_HEADER, duration.to_string()));
+  }
+  if let Some(tag) = &metadata.tag {
+    builder.insert_header((ARTIFACT_TAG_HEADER, tag.as_str()));
+  }
+}
+
+/// Advertises range requests support, which is only available for artifacts
+/// stored as they were received.
+pub fn insert_accept_ranges(builder: &mut HttpResponseBuilder, metadata: &ArtifactMetadata) {
+  let unit = if metadata.stored_as_received() {
+    "bytes"
+  } else {
+    "none"
+  };
+  builder.insert_header((ACCEPT_RANGES, unit));
+}
+
+/// `ETag` and `Last-Modified` of a stored artifact, used to answer conditional requests.
+pub struct ArtifactValidators {
+  etag: Option<EntityTag>,
+  last_modified: SystemTime,
+}
+
+impl ArtifactValidators {
+  /// The checksum of the artifact is used as a strong `ETag` when known, since it
+  /// doesn't change when the artifact is copied to another store, the store `ETag` otherwise.
+  pub fn new(meta: &ObjectMeta, metadata: &ArtifactMetadata) -> Self {
+    let etag = metadata
+      .sha256
+      .clone()
+      .or_else(|| {
+        let etag = meta.e_tag.as_deref()?;
+        Some(etag.trim_start_matches("W/").trim_matches('"').to_string())
+      })
+      .filter(|etag| !etag.is_empty() && !etag.contains('"'))
+      .map(EntityTag::new_strong);
+    // HTTP dates have a one second resolution
+    let seconds = meta.last_modified.timestamp().max(0) as u64;
+    ArtifactValidators {
+      etag,
+      last_modified: UNIX_EPOCH + Duration::from_secs(seconds),
+    }
+  }
+
+  /// Whether the request carries `If-None-Match` or `If-Modified-Since`.
+  pub fn requested(req: &HttpRequest) -> bool {
+    let headers = req.headers();
+    headers.contains_key(IF_NONE_MATCH) || headers.contains_key(IF_MODIFIED_SINCE)
+  }
+
+  /// Whether the copy the client holds is still valid, `If-Modified-Since` is
+  /// ignored when `If-None-Match` is sent as per RFC 9110.
+  pub fn not_modified(&self, req: &HttpRequest) -> bool {
+    if req.headers().contains_key(IF_NONE_MATCH) {
+      return match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(etags)) => self
+          .etag
+          .as_ref()
+          .is_some_and(|etag| etags.iter().any(|other| other.weak_eq(etag))),
+        Err(_) => false,
+      };
+    }
+    match IfModifiedSince::parse(req) {
+      Ok(IfModifiedSince(since)) => self.last_modified <= SystemTime::from(since),
+      Err(_) => false,
+    }
+  }
+
+  /// Whether the `If-Range` sent along a `Range` still matches the artifact, in
+  /// which case the range can be served, otherwise the whole artifact is.
+  fn if_range_matches(&self, req: &HttpRequest) -> bool {
+    if !req.headers().contains_key(IF_RANGE) {
+      return true;
+    }
+    match IfRange::parse(req) {
+      Ok(IfRange::EntityTag(etag)) => self
+        .etag
+        .as_ref()
+        .is_some_and(|own| !etag.weak && own.strong_eq(&etag)),
+      Ok(IfRange::Date(date)) => self.last_modified == SystemTime::from(date),
+      Err(_) => false,
+    }
+  }
+
+  pub fn insert(&self, builder: &mut HttpResponseBuilder) {
+    if let Some(etag) = &self.etag {
+      builder.insert_header((ETAG, etag.to_string()));
+    }
+    builder.insert_header((LAST_MODIFIED, HttpDate::from(self.last_modified)));
+  }
+
+  pub fn not_modified_response(&self) -> HttpResponse {
+    let mut response = HttpResponse::NotModified();
+    self.insert(&mut response);
+    response.finish()
+  }
+}
+
+/// The single byte range requested with `Range`, `None` when the whole artifact
+/// should be sent: no, multiple or malformed ranges, an outdated `If-Range` or an
+/// artifact stored encoded, whose stored bytes don't map to the artifact ones.
+pub fn artifact_range_or_416(
+  req: &HttpRequest,
+  meta: &ObjectMeta,
+  metadata: &ArtifactMetadata,
+  validators: &ArtifactValidators,
+) -> Result<Option<std::ops::Range<u64>>, Error> {
+  if !req.headers().contains_key(RANGE)
+    || !metadata.stored_as_received()
+    || !validators.if_range_matches(req)
+  {
+    return Ok(None);
+  }
+  let length = metadata.content_length(meta);
+  let spec: ByteRangeSpec = match Range::parse(req) {
+    Ok(Range::Bytes(mut specs)) if specs.len() == 1 => specs.remove(0),
+    // a `Range` that can't be parsed is ignored (RFC 9110, 14.2)
+    _ => return Ok(None),
+  };
+  match spec.to_satisfiable_range(length) {
+    Some((start, end)) => Ok(Some(start..end + 1)),
+    None => Err(Error::RangeNotSatisfiable(length)),
   }
-  Ok(true)
 }
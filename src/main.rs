@@ -4,32 +4,84 @@ use actix_web::{
   web::{Data, PayloadConfig},
   App, HttpServer,
 };
+use clap::Parser;
 use log::info;
-use std::{env::args, path::Path, sync::Arc};
+use std::{process::ExitCode, sync::Arc};
 
-use crate::config::{get_port, Config};
-use crate::handlers::{artifacts, turborepo};
+use crate::cli::{Cli, Command, CommandError};
+use crate::config::Config;
+use crate::events::EventStore;
+use crate::handlers::{admin, artifacts, turborepo};
+use crate::storage::{gc::FileGc, retention::RetentionSweeper, StorageStore};
 
 pub mod auth;
+pub mod cli;
 pub mod config;
+pub mod error;
+pub mod events;
 pub mod handlers;
 pub mod helpers;
+pub mod metrics;
 pub mod storage;
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-  // Load the environment variables from the .env file
-  let env_file = args().nth(1).unwrap_or(".env".to_string());
-  dotenvy::from_path(Path::new(&env_file)).ok();
-  // Initialize the logger
-  env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-  let config = Arc::new(Config::from_env().expect("error loading config from environment"));
-  let port = get_port();
+async fn main() -> ExitCode {
+  match run(Cli::parse()).await {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      // printed rather than logged, the logger isn't set up when the configuration
+      // couldn't be loaded or isn't needed
+      eprintln!("{}", e.message);
+      ExitCode::from(e.code)
+    }
+  }
+}
+
+async fn run(cli: Cli) -> Result<(), CommandError> {
+  let Cli {
+    config,
+    env_file,
+    command,
+  } = cli;
+  let config = || cli::load_config(config.as_deref(), &env_file);
+  match command.unwrap_or(Command::Serve) {
+    Command::Serve => serve(Arc::new(config()?)).await,
+    Command::Check => cli::check(&config()?).await,
+    Command::Gc { dry_run } => cli::gc(&config()?, dry_run).await,
+    Command::Migrate(args) => cli::migrate(&config()?, args).await,
+    // tokens are generated without any configuration
+    Command::Token(command) => cli::token(command),
+  }
+}
+
+async fn serve(config: Arc<Config>) -> Result<(), CommandError> {
+  let port = config.port;
+  let storage = Data::new(StorageStore::new(&config)?);
+  if let Some(gc) = FileGc::from_config(&config, config.gc_dry_run)? {
+    info!(
+      "Starting file cache garbage collection every {:?}",
+      config.gc_interval
+    );
+    gc.with_storage(storage.clone().into_inner()).spawn();
+  }
+  let sweeper = RetentionSweeper::from_config(&config, storage.clone().into_inner(), false);
+  if let Some(sweeper) = sweeper {
+    info!(
+      "Starting retention sweeps every {:?}",
+      config.retention_interval
+    );
+    sweeper.spawn();
+  }
+  let events = Data::new(EventStore::new(&config)?);
   info!(
     "Using {} storage provider with bucket {} at {}",
     config.storage_provider, config.bucket_name, config.fs_cache_path
   );
+  if config.admin_tokens.is_empty() {
+    info!("Admin API is disabled, set ADMIN_TOKENS to enable it");
+  }
   info!("Starting HTTP server at http://localhost:{}", port);
+  let max_artifact_size = config.max_artifact_size;
   // Create and Start the HTTP server
   HttpServer::new(move || {
     App::new()
@@ -41,11 +93,14 @@ async fn main() -> std::io::Result<()> {
           .allow_any_origin(),
       )
       .app_data(Data::new(config.clone()))
+      .configure(metrics::configure)
       .configure(turborepo::configure)
-      .configure(artifacts::configure(&config))
-      .app_data(PayloadConfig::new(104857600))
+      .configure(artifacts::configure(storage.clone(), events.clone()))
+      .configure(admin::configure(storage.clone()))
+      .app_data(PayloadConfig::new(max_artifact_size))
   })
   .bind(("0.0.0.0", port))?
   .run()
-  .await
+  .await?;
+  Ok(())
 }
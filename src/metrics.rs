@@ -0,0 +1,318 @@
+use std::{
+  collections::HashSet,
+  future::{ready, Ready},
+  pin::Pin,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, LazyLock, Mutex,
+  },
+  task::{Context, Poll},
+  time::Instant,
+};
+
+use actix_web::{
+  body::{BodySize, MessageBody},
+  dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+  http::StatusCode,
+  web::{get, resource, Query, ServiceConfig},
+  Error, HttpMessage, HttpResponse, Responder,
+};
+use futures_util::{future::LocalBoxFuture, Stream};
+use prometheus::{
+  Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+  TextEncoder,
+};
+
+use crate::auth::{AdminAuth, Principal};
+use crate::helpers::{internal_server_error, GetArtifactQuery};
+
+// Maximum number of distinct teams used as a label value, the others are reported as `other`.
+const MAX_TEAM_LABELS: usize = 100;
+
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static ARTIFACT_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+  register(IntCounterVec::new(
+    Opts::new(
+      "turbo_cache_requests_total",
+      "Number of artifact requests by method, route, status and team.",
+    ),
+    &["method", "route", "status", "team"],
+  ))
+});
+
+pub static ARTIFACT_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+  register(HistogramVec::new(
+    HistogramOpts::new(
+      "turbo_cache_request_duration_seconds",
+      "Time spent handling artifact requests by method and route.",
+    ),
+    &["method", "route"],
+  ))
+});
+
+pub static BYTES_IN: LazyLock<IntCounterVec> = LazyLock::new(|| {
+  register(IntCounterVec::new(
+    Opts::new(
+      "turbo_cache_bytes_in_total",
+      "Number of bytes received in request bodies by team.",
+    ),
+    &["team"],
+  ))
+});
+
+pub static BYTES_OUT: LazyLock<IntCounterVec> = LazyLock::new(|| {
+  register(IntCounterVec::new(
+    Opts::new(
+      "turbo_cache_bytes_out_total",
+      "Number of bytes sent in response bodies by team.",
+    ),
+    &["team"],
+  ))
+});
+
+pub static IN_FLIGHT_REQUESTS: LazyLock<IntGauge> = LazyLock::new(|| {
+  register(IntGauge::new(
+    "turbo_cache_in_flight_requests",
+    "Number of artifact requests being handled.",
+  ))
+});
+
+pub static STORAGE_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+  register(HistogramVec::new(
+    HistogramOpts::new(
+      "turbo_cache_storage_operation_duration_seconds",
+      "Latency of storage backend operations by operation and result.",
+    ),
+    &["operation", "result"],
+  ))
+});
+
+pub static AUTH_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+  register(IntCounterVec::new(
+    Opts::new(
+      "turbo_cache_auth_failures_total",
+      "Number of rejected requests by reason.",
+    ),
+    &["reason"],
+  ))
+});
+
+pub static MEMORY_EVICTIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+  register(IntCounter::new(
+    "turbo_cache_memory_evictions_total",
+    "Number of objects evicted from the memory storage provider.",
+  ))
+});
+
+pub static MEMORY_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+  register(IntGauge::new(
+    "turbo_cache_memory_bytes",
+    "Number of bytes held by the memory storage provider.",
+  ))
+});
+
+pub static TIER_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+  register(IntCounterVec::new(
+    Opts::new(
+      "turbo_cache_tier_requests_total",
+      "Number of reads served by the local cache tier by result (hit or miss).",
+    ),
+    &["result"],
+  ))
+});
+
+pub static CORRUPTED_ARTIFACTS: LazyLock<IntCounter> = LazyLock::new(|| {
+  register(IntCounter::new(
+    "turbo_cache_corrupted_artifacts_total",
+    "Number of artifacts that failed checksum verification or decryption and were quarantined.",
+  ))
+});
+
+static TEAM_LABELS: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+fn register<M: prometheus::core::Collector + Clone + 'static>(
+  metric: Result<M, prometheus::Error>,
+) -> M {
+  let metric = metric.expect("invalid metric definition");
+  REGISTRY
+    .register(Box::new(metric.clone()))
+    .expect("metric registered twice");
+  metric
+}
+
+/// Returns `team` as a label value while keeping the number of distinct values bounded.
+/// Only `trusted` teams, which a request was authorized for and succeeded on, get a
+/// label of their own so made-up team ids can't use up the label values.
+pub fn team_label(team: Option<&str>, trusted: bool) -> String {
+  let Some(team) = team else {
+    return String::new();
+  };
+  let mut teams = TEAM_LABELS.lock().unwrap_or_else(|e| e.into_inner());
+  if teams.contains(team) {
+    return team.to_string();
+  }
+  if trusted && teams.len() < MAX_TEAM_LABELS {
+    teams.insert(team.to_string());
+    return team.to_string();
+  }
+  "other".to_string()
+}
+
+/// Records the result and latency of a storage operation.
+pub fn observe_storage<T, E>(operation: &str, started: Instant, result: &Result<T, E>) {
+  let result = if result.is_ok() { "ok" } else { "error" };
+  STORAGE_DURATION
+    .with_label_values(&[operation, result])
+    .observe(started.elapsed().as_secs_f64());
+}
+
+/// Serves the metrics at `/metrics` to admin tokens only, since they name teams.
+pub fn configure(cfg: &mut ServiceConfig) {
+  cfg.service(
+    resource("/metrics")
+      .wrap(AdminAuth)
+      .route(get().to(get_metrics)),
+  );
+}
+
+pub async fn get_metrics() -> impl Responder {
+  let mut buffer = vec![];
+  let encoder = TextEncoder::new();
+  match encoder.encode(&REGISTRY.gather(), &mut buffer) {
+    Ok(()) => HttpResponse::Ok()
+      .content_type(encoder.format_type())
+      .body(buffer),
+    Err(e) => internal_server_error(format!("Failed to encode metrics: {}", e)),
+  }
+}
+
+// Keeps the in-flight gauge right even when the request future is dropped early.
+struct InFlightGuard;
+
+impl InFlightGuard {
+  fn new() -> Self {
+    IN_FLIGHT_REQUESTS.inc();
+    InFlightGuard
+  }
+}
+
+impl Drop for InFlightGuard {
+  fn drop(&mut self) {
+    IN_FLIGHT_REQUESTS.dec();
+  }
+}
+
+// Counts the bytes read from a request payload.
+struct CountingPayload {
+  payload: Payload,
+  count: Arc<AtomicUsize>,
+}
+
+impl Stream for CountingPayload {
+  type Item = <Payload as Stream>::Item;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let poll = Pin::new(&mut self.payload).poll_next(cx);
+    if let Poll::Ready(Some(Ok(chunk))) = &poll {
+      self.count.fetch_add(chunk.len(), Ordering::Relaxed);
+    }
+    poll
+  }
+}
+
+pub struct Metrics;
+
+impl<S, B> Transform<S, ServiceRequest> for Metrics
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: MessageBody + 'static,
+{
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = MetricsMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ready(Ok(MetricsMiddleware { service }))
+  }
+}
+
+pub struct MetricsMiddleware<S> {
+  service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for MetricsMiddleware<S>
+where
+  S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: MessageBody + 'static,
+{
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+  forward_ready!(service);
+
+  fn call(&self, mut request: ServiceRequest) -> Self::Future {
+    let guard = InFlightGuard::new();
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+      .match_pattern()
+      .unwrap_or_else(|| "unmatched".to_string());
+    let team = Query::<GetArtifactQuery>::from_query(request.query_string())
+      .ok()
+      .and_then(|query| query.into_inner().team_id());
+
+    let bytes_in = Arc::new(AtomicUsize::new(0));
+    let payload = CountingPayload {
+      payload: request.take_payload(),
+      count: bytes_in.clone(),
+    };
+    request.set_payload(Payload::Stream {
+      payload: Box::pin(payload),
+    });
+
+    let res = self.service.call(request);
+    Box::pin(async move {
+      let _guard = guard;
+      let res = res.await;
+      let status = match &res {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+      };
+      // rejected requests could carry any team, don't let them grow the label set
+      let team = if status.is_client_error() && status != StatusCode::NOT_FOUND {
+        String::new()
+      } else {
+        let authorized = res.as_ref().is_ok_and(|res| {
+          let extensions = res.request().extensions();
+          let principal = extensions.get::<Principal>();
+          team
+            .as_deref()
+            .is_some_and(|team| principal.is_some_and(|p| p.can_access(team)))
+        });
+        team_label(team.as_deref(), authorized && status.is_success())
+      };
+      ARTIFACT_REQUESTS
+        .with_label_values(&[&method, &route, status.as_str(), &team])
+        .inc();
+      ARTIFACT_REQUEST_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+      BYTES_IN
+        .with_label_values(&[&team])
+        .inc_by(bytes_in.load(Ordering::Relaxed) as u64);
+      // HEAD responses advertise the artifact size without sending it
+      if let (Ok(res), false) = (&res, method == "HEAD") {
+        if let BodySize::Sized(size) = res.response().body().size() {
+          BYTES_OUT.with_label_values(&[&team]).inc_by(size);
+        }
+      }
+      res
+    })
+  }
+}
@@ -0,0 +1,147 @@
+use std::fmt::Write;
+
+use actix_web::web::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+use ring::digest::{Context, SHA256};
+
+use crate::error::Error;
+
+/// Incremental SHA-256 of an artifact, hex encoded once finished.
+pub struct Sha256(Context);
+
+impl Sha256 {
+  pub fn new() -> Self {
+    Sha256(Context::new(&SHA256))
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    self.0.update(data);
+  }
+
+  pub fn finish(self) -> String {
+    self
+      .0
+      .finish()
+      .as_ref()
+      .iter()
+      .fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+      })
+  }
+}
+
+/// Passes `input` through while hashing it, the last chunk is only yielded once
+/// the hash matched `expected` so a client never receives a complete corrupted
+/// artifact. `on_mismatch` is called and [`Error::Corrupted`] yielded otherwise.
+pub fn verify_sha256<S, F>(
+  input: S,
+  path: &str,
+  expected: &str,
+  on_mismatch: F,
+) -> impl Stream<Item = Result<Bytes, Error>>
+where
+  S: Stream<Item = Result<Bytes, Error>> + Unpin,
+  F: FnOnce(),
+{
+  struct State<S, F> {
+    input: S,
+    hash: Sha256,
+    pending: Option<Bytes>,
+    on_mismatch: F,
+  }
+  let state = State {
+    input,
+    hash: Sha256::new(),
+    pending: None,
+    on_mismatch,
+  };
+  let path = path.to_string();
+  let expected = expected.to_string();
+  stream::unfold(Some(state), move |state| {
+    let path = path.clone();
+    let expected = expected.clone();
+    async move {
+      let mut state = state?;
+      loop {
+        match state.input.next().await {
+          Some(Ok(chunk)) => {
+            state.hash.update(&chunk);
+            if let Some(pending) = state.pending.replace(chunk) {
+              return Some((Ok(pending), Some(state)));
+            }
+          }
+          Some(Err(e)) => return Some((Err(e), None)),
+          None => {
+            let actual = state.hash.finish();
+            if actual != expected {
+              (state.on_mismatch)();
+              let e = Error::Corrupted(format!(
+                "{} has checksum {} instead of {}",
+                path, actual, expected
+              ));
+              return Some((Err(e), None));
+            }
+            return state.pending.map(|pending| (Ok(pending), None));
+          }
+        }
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod checksum_tests {
+  use std::cell::Cell;
+
+  use futures_util::TryStreamExt;
+
+  use super::*;
+
+  fn chunks(data: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+    stream::iter(
+      data
+        .iter()
+        .map(|chunk| Ok(Bytes::from_static(chunk)))
+        .collect::<Vec<_>>(),
+    )
+  }
+
+  #[test]
+  fn test_sha256() {
+    let mut hash = Sha256::new();
+    hash.update(b"te");
+    hash.update(b"st");
+    assert_eq!(
+      hash.finish(),
+      "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+    );
+  }
+
+  #[actix_web::test]
+  async fn test_verify_sha256() {
+    let expected = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    let mismatches = Cell::new(0);
+    let data: Vec<Bytes> = verify_sha256(chunks(&[b"te", b"st"]), "team/123", expected, || {
+      mismatches.set(mismatches.get() + 1)
+    })
+    .try_collect()
+    .await
+    .unwrap();
+    assert_eq!(data.concat(), b"test");
+
+    let mut stream = Box::pin(verify_sha256(
+      chunks(&[b"te", b"sx"]),
+      "team/123",
+      expected,
+      || mismatches.set(mismatches.get() + 1),
+    ));
+    assert_eq!(stream.next().await.unwrap().unwrap(), "te");
+    assert!(matches!(
+      stream.next().await,
+      Some(Err(Error::Corrupted(_)))
+    ));
+    assert!(stream.next().await.is_none());
+    assert_eq!(mismatches.get(), 1);
+  }
+}
@@ -0,0 +1,134 @@
+use std::io::Write;
+
+use actix_web::web::Bytes;
+use futures_util::{
+  future::ready,
+  stream::{self, Stream, StreamExt, TryStreamExt},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+// Frame header every zstd stream starts with.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Encoding of an artifact at rest, recorded in its metadata so it can be decoded on read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+  Zstd,
+}
+
+/// Whether `chunk` starts a zstd stream, compressing it again would only waste CPU.
+pub fn is_zstd(chunk: &[u8]) -> bool {
+  chunk.starts_with(&ZSTD_MAGIC)
+}
+
+fn codec_error(e: std::io::Error) -> Error {
+  Error::Internal(format!("error transcoding artifact: {}", e))
+}
+
+// Feeds every chunk of `input` into `writer` and yields whatever it wrote to its
+// buffer so far, `finish` flushes the remaining output once `input` is exhausted.
+fn transcode<'a, S, W>(
+  input: S,
+  writer: W,
+  buffer: fn(&mut W) -> &mut Vec<u8>,
+  finish: fn(W) -> std::io::Result<Vec<u8>>,
+) -> impl Stream<Item = Result<Bytes, Error>> + 'a
+where
+  S: Stream<Item = Result<Bytes, Error>> + Unpin + 'a,
+  W: Write + 'a,
+{
+  stream::unfold(Some((input, writer)), move |state| async move {
+    let (mut input, mut writer) = state?;
+    match input.next().await {
+      Some(Ok(chunk)) => {
+        let output = writer
+          .write_all(&chunk)
+          .map(|()| Bytes::from(std::mem::take(buffer(&mut writer))))
+          .map_err(codec_error);
+        let next = output.is_ok().then_some((input, writer));
+        Some((output, next))
+      }
+      Some(Err(e)) => Some((Err(e), None)),
+      None => Some((finish(writer).map(Bytes::from).map_err(codec_error), None)),
+    }
+  })
+  .try_filter(|chunk| ready(!chunk.is_empty()))
+}
+
+/// Compresses `input` with zstd at `level` one chunk at a time.
+pub fn zstd_encode<'a, S>(
+  input: S,
+  level: i32,
+) -> Result<impl Stream<Item = Result<Bytes, Error>> + 'a, Error>
+where
+  S: Stream<Item = Result<Bytes, Error>> + Unpin + 'a,
+{
+  let encoder = zstd::stream::write::Encoder::new(vec![], level).map_err(codec_error)?;
+  Ok(transcode(
+    input,
+    encoder,
+    |encoder| encoder.get_mut(),
+    |encoder| encoder.finish(),
+  ))
+}
+
+/// Decompresses the zstd stream `input` one chunk at a time.
+pub fn zstd_decode<'a, S>(input: S) -> Result<impl Stream<Item = Result<Bytes, Error>> + 'a, Error>
+where
+  S: Stream<Item = Result<Bytes, Error>> + Unpin + 'a,
+{
+  let decoder = zstd::stream::write::Decoder::new(vec![]).map_err(codec_error)?;
+  Ok(transcode(
+    input,
+    decoder,
+    |decoder| decoder.get_mut(),
+    |mut decoder| {
+      decoder.flush()?;
+      Ok(decoder.into_inner())
+    },
+  ))
+}
+
+#[cfg(test)]
+mod codec_tests {
+  use super::*;
+
+  async fn collect<S: Stream<Item = Result<Bytes, Error>>>(stream: S) -> Vec<u8> {
+    let chunks: Vec<Bytes> = stream.try_collect().await.unwrap();
+    chunks.concat()
+  }
+
+  #[actix_web::test]
+  async fn test_zstd_round_trip() {
+    let data = b"turbo artifact ".repeat(10_000);
+    let chunks = data
+      .chunks(1000)
+      .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+      .collect::<Vec<_>>();
+    let compressed = collect(zstd_encode(stream::iter(chunks), 3).unwrap()).await;
+    assert!(is_zstd(&compressed));
+    assert!(compressed.len() < data.len() / 10);
+    let chunks = compressed
+      .chunks(100)
+      .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+      .collect::<Vec<_>>();
+    let decompressed = collect(zstd_decode(stream::iter(chunks)).unwrap()).await;
+    assert_eq!(decompressed, data);
+  }
+
+  #[actix_web::test]
+  async fn test_zstd_encode_forwards_errors() {
+    let chunks = vec![
+      Ok(Bytes::from_static(b"data")),
+      Err(Error::PayloadTooLarge("too large".to_string())),
+    ];
+    let result: Result<Vec<Bytes>, Error> = zstd_encode(stream::iter(chunks), 3)
+      .unwrap()
+      .try_collect()
+      .await;
+    assert!(matches!(result, Err(Error::PayloadTooLarge(_))));
+  }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use actix_web::web::Bytes;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::stream::{self, Stream, StreamExt};
+use ring::{
+  aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+  rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+// Artifacts are sealed in segments of this many plaintext bytes so they can be
+// streamed, each segment carrying its own authentication tag.
+const SEGMENT_SIZE: usize = 64 * 1024;
+const KEY_LEN: usize = 32;
+
+/// How an artifact was encrypted, stored in its metadata.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Encryption {
+  /// Id of the key in the key file the data key is wrapped with.
+  pub key_id: String,
+  /// Nonce and data key sealed with the key `key_id`, base64 encoded.
+  pub wrapped_key: String,
+}
+
+#[derive(Deserialize)]
+struct KeyFile {
+  active: String,
+  keys: HashMap<String, String>,
+}
+
+fn corrupted(path: &str) -> Error {
+  Error::Corrupted(format!("{} failed authentication", path))
+}
+
+// Segments are numbered and the last one is flagged, so segments can't be
+// reordered, dropped or the artifact truncated without failing authentication.
+fn segment_nonce(index: u64, last: bool) -> Nonce {
+  let mut nonce = [0u8; NONCE_LEN];
+  nonce[..8].copy_from_slice(&index.to_be_bytes());
+  nonce[8] = last as u8;
+  Nonce::assume_unique_for_key(nonce)
+}
+
+fn aead_key(key: &[u8]) -> Result<LessSafeKey, Error> {
+  UnboundKey::new(&AES_256_GCM, key)
+    .map(LessSafeKey::new)
+    .map_err(|_| Error::Internal("encryption keys must be 32 bytes long".to_string()))
+}
+
+/// Encryption keys by id, new artifacts are encrypted with the active one while
+/// the others are kept to decrypt artifacts written before a rotation.
+///
+/// Every artifact is encrypted with its own random data key (AES-256-GCM), which is
+/// itself encrypted with a key of the keyring and stored in the artifact metadata.
+pub struct Keyring {
+  active: String,
+  keys: HashMap<String, LessSafeKey>,
+  random: SystemRandom,
+}
+
+impl Keyring {
+  pub fn new(active: &str, keys: HashMap<String, [u8; KEY_LEN]>) -> Result<Self, Error> {
+    if !keys.contains_key(active) {
+      return Err(Error::Internal(format!(
+        "active encryption key {} is missing",
+        active
+      )));
+    }
+    let keys = keys
+      .into_iter()
+      .map(|(id, key)| Ok((id, aead_key(&key)?)))
+      .collect::<Result<_, Error>>()?;
+    Ok(Keyring {
+      active: active.to_string(),
+      keys,
+      random: SystemRandom::new(),
+    })
+  }
+
+  /// Loads a JSON key file such as `{"active": "2024-10", "keys": {"2024-10": "<base64>"}}`,
+  /// keys being 32 random bytes, e.g. generated with `openssl rand -base64 32`.
+  pub fn from_file(path: &str) -> Result<Self, Error> {
+    let invalid = |e: String| Error::Internal(format!("invalid key file {}: {}", path, e));
+    let content = std::fs::read(path).map_err(|e| invalid(e.to_string()))?;
+    let file: KeyFile = serde_json::from_slice(&content).map_err(|e| invalid(e.to_string()))?;
+    let keys = file
+      .keys
+      .into_iter()
+      .map(|(id, key)| {
+        let key = STANDARD
+          .decode(key.trim())
+          .map_err(|e| invalid(format!("key {} isn't base64: {}", id, e)))?;
+        let key = key
+          .try_into()
+          .map_err(|_| invalid(format!("key {} must be {} bytes long", id, KEY_LEN)))?;
+        Ok((id, key))
+      })
+      .collect::<Result<_, Error>>()?;
+    Keyring::new(&file.active, keys).map_err(|e| invalid(e.to_string()))
+  }
+
+  fn random<const N: usize>(&self) -> Result<[u8; N], Error> {
+    let mut bytes = [0u8; N];
+    self
+      .random
+      .fill(&mut bytes)
+      .map_err(|_| Error::Internal("error generating random bytes".to_string()))?;
+    Ok(bytes)
+  }
+
+  /// Encrypts `input` with a new data key, the returned [`Encryption`] must be
+  /// stored along the artifact to decrypt it. The data key is bound to `path` so
+  /// the artifact can't be moved to another team or hash.
+  pub fn encrypt<'a, S>(
+    &self,
+    path: &str,
+    input: S,
+  ) -> Result<(Encryption, impl Stream<Item = Result<Bytes, Error>> + 'a), Error>
+  where
+    S: Stream<Item = Result<Bytes, Error>> + Unpin + 'a,
+  {
+    let data_key: [u8; KEY_LEN] = self.random()?;
+    let nonce: [u8; NONCE_LEN] = self.random()?;
+    let mut wrapped = data_key.to_vec();
+    self.keys[&self.active]
+      .seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::from(path.as_bytes()),
+        &mut wrapped,
+      )
+      .map_err(|_| Error::Internal("error wrapping the data key".to_string()))?;
+    let encryption = Encryption {
+      key_id: self.active.clone(),
+      wrapped_key: STANDARD.encode([nonce.as_slice(), &wrapped].concat()),
+    };
+    let key = aead_key(&data_key)?;
+    let stream = segments(input, SEGMENT_SIZE, move |index, last, mut segment| {
+      key
+        .seal_in_place_append_tag(segment_nonce(index, last), Aad::empty(), &mut segment)
+        .map_err(|_| Error::Internal("error encrypting artifact".to_string()))?;
+      Ok(segment)
+    });
+    Ok((encryption, stream))
+  }
+
+  /// Decrypts an artifact encrypted by [`Keyring::encrypt`], any tampering with it
+  /// or its metadata yields [`Error::Corrupted`].
+  pub fn decrypt<'a, S>(
+    &self,
+    path: &str,
+    encryption: &Encryption,
+    input: S,
+  ) -> Result<impl Stream<Item = Result<Bytes, Error>> + 'a, Error>
+  where
+    S: Stream<Item = Result<Bytes, Error>> + Unpin + 'a,
+  {
+    let key = self.keys.get(&encryption.key_id).ok_or_else(|| {
+      Error::Corrupted(format!(
+        "{} is encrypted with the unknown key {}",
+        path, encryption.key_id
+      ))
+    })?;
+    let wrapped = STANDARD
+      .decode(&encryption.wrapped_key)
+      .map_err(|_| corrupted(path))?;
+    if wrapped.len() < NONCE_LEN {
+      return Err(corrupted(path));
+    }
+    let (nonce, wrapped) = wrapped.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| corrupted(path))?;
+    let mut wrapped = wrapped.to_vec();
+    let data_key = key
+      .open_in_place(nonce, Aad::from(path.as_bytes()), &mut wrapped)
+      .map_err(|_| corrupted(path))?;
+    let key = aead_key(data_key).map_err(|_| corrupted(path))?;
+    let path = path.to_string();
+    let tag_len = AES_256_GCM.tag_len();
+    Ok(segments(
+      input,
+      SEGMENT_SIZE + tag_len,
+      move |index, last, mut segment| {
+        let plaintext = key
+          .open_in_place(segment_nonce(index, last), Aad::empty(), &mut segment)
+          .map_err(|_| corrupted(&path))?;
+        let len = plaintext.len();
+        segment.truncate(len);
+        Ok(segment)
+      },
+    ))
+  }
+}
+
+// Splits `input` into segments of `size` bytes (the last one being shorter or
+// empty) and yields `seal(index, last, segment)` for each of them.
+fn segments<'a, S, F>(
+  input: S,
+  size: usize,
+  seal: F,
+) -> impl Stream<Item = Result<Bytes, Error>> + 'a
+where
+  S: Stream<Item = Result<Bytes, Error>> + Unpin + 'a,
+  F: FnMut(u64, bool, Vec<u8>) -> Result<Vec<u8>, Error> + 'a,
+{
+  struct State<S, F> {
+    input: S,
+    buffer: Vec<u8>,
+    index: u64,
+    seal: F,
+  }
+  let state = State {
+    input,
+    buffer: vec![],
+    index: 0,
+    seal,
+  };
+  stream::unfold(Some(state), move |state| async move {
+    let mut state = state?;
+    loop {
+      // a full segment is only known not to be the last one once more data follows
+      if state.buffer.len() > size {
+        let rest = state.buffer.split_off(size);
+        let segment = std::mem::replace(&mut state.buffer, rest);
+        let result = (state.seal)(state.index, false, segment).map(Bytes::from);
+        state.index += 1;
+        let next = result.is_ok().then_some(state);
+        return Some((result, next));
+      }
+      match state.input.next().await {
+        Some(Ok(chunk)) => state.buffer.extend_from_slice(&chunk),
+        Some(Err(e)) => return Some((Err(e), None)),
+        None => {
+          let segment = std::mem::take(&mut state.buffer);
+          let result = (state.seal)(state.index, true, segment).map(Bytes::from);
+          return Some((result, None));
+        }
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod crypto_tests {
+  use futures_util::TryStreamExt;
+
+  use super::*;
+
+  fn keyring(active: &str) -> Keyring {
+    let keys = HashMap::from([
+      ("old".to_string(), [1u8; 32]),
+      ("new".to_string(), [2u8; 32]),
+    ]);
+    Keyring::new(active, keys).unwrap()
+  }
+
+  fn chunks(data: &[u8]) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+    let chunks: Vec<_> = data
+      .chunks(10_000)
+      .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+      .collect();
+    stream::iter(chunks)
+  }
+
+  async fn encrypt(keyring: &Keyring, path: &str, data: &[u8]) -> (Encryption, Vec<u8>) {
+    let (encryption, stream) = keyring.encrypt(path, chunks(data)).unwrap();
+    let ciphertext: Vec<Bytes> = stream.try_collect().await.unwrap();
+    (encryption, ciphertext.concat())
+  }
+
+  async fn decrypt(
+    keyring: &Keyring,
+    path: &str,
+    encryption: &Encryption,
+    data: &[u8],
+  ) -> Result<Vec<u8>, Error> {
+    let stream = keyring.decrypt(path, encryption, chunks(data))?;
+    let plaintext: Vec<Bytes> = stream.try_collect().await?;
+    Ok(plaintext.concat())
+  }
+
+  #[actix_web::test]
+  async fn test_round_trip_after_rotation() {
+    let data = vec![7u8; 3 * SEGMENT_SIZE + 5];
+    let (encryption, ciphertext) = encrypt(&keyring("old"), "team/123", &data).await;
+    assert_eq!(encryption.key_id, "old");
+    assert_ne!(ciphertext[..data.len()], data[..]);
+    let plaintext = decrypt(&keyring("new"), "team/123", &encryption, &ciphertext).await;
+    assert_eq!(plaintext.unwrap(), data);
+
+    let (encryption, ciphertext) = encrypt(&keyring("new"), "team/123", b"").await;
+    let plaintext = decrypt(&keyring("old"), "team/123", &encryption, &ciphertext).await;
+    assert_eq!(plaintext.unwrap(), b"");
+  }
+
+  #[actix_web::test]
+  async fn test_tampering_is_detected() {
+    let keyring = keyring("new");
+    let data = vec![7u8; 2 * SEGMENT_SIZE];
+    let (encryption, ciphertext) = encrypt(&keyring, "team/123", &data).await;
+
+    let mut flipped = ciphertext.clone();
+    flipped[SEGMENT_SIZE + 100] ^= 1;
+    let truncated = &ciphertext[..SEGMENT_SIZE + AES_256_GCM.tag_len()];
+    for tampered in [&flipped[..], truncated] {
+      let result = decrypt(&keyring, "team/123", &encryption, tampered).await;
+      assert!(matches!(result, Err(Error::Corrupted(_))));
+    }
+    // moved to another team
+    let result = decrypt(&keyring, "other/123", &encryption, &ciphertext).await;
+    assert!(matches!(result, Err(Error::Corrupted(_))));
+  }
+}
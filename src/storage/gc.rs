@@ -0,0 +1,279 @@
+use std::{
+  collections::HashMap,
+  fmt::Display,
+  sync::Arc,
+  time::{Duration, SystemTime},
+};
+
+use futures_util::TryStreamExt;
+use log::{error, info, warn};
+use object_store::{local::LocalFileSystem, path::Path, ObjectStore};
+
+use super::{get_local_file_system, StorageStore, METADATA_SUFFIX};
+use crate::{
+  config::{CacheTier, Config, StorageProvider},
+  error::Error,
+};
+
+/// Retention limits enforced by [`FileGc`].
+#[derive(Debug, Clone, Default)]
+pub struct GcOptions {
+  /// Artifacts not accessed for longer than this are removed.
+  pub max_age: Option<Duration>,
+  /// Oldest accessed artifacts are removed until the cache fits in this many bytes.
+  pub max_size: Option<u64>,
+  /// Only log what would be removed.
+  pub dry_run: bool,
+  /// Time between two background runs.
+  pub interval: Duration,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcReport {
+  pub removed: usize,
+  pub reclaimed_bytes: u64,
+  pub kept: usize,
+  pub kept_bytes: u64,
+}
+
+impl Display for GcReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "removed {} artifacts ({} bytes), kept {} artifacts ({} bytes)",
+      self.removed, self.reclaimed_bytes, self.kept, self.kept_bytes
+    )
+  }
+}
+
+// An artifact along with its metadata sidecar.
+struct Entry {
+  key: String,
+  locations: Vec<Path>,
+  size: u64,
+  accessed: SystemTime,
+}
+
+/// Garbage collector for the `File` storage provider.
+pub struct FileGc {
+  store: Arc<LocalFileSystem>,
+  options: GcOptions,
+  storage: Option<Arc<StorageStore>>,
+}
+
+impl FileGc {
+  pub fn new(store: Arc<LocalFileSystem>, options: GcOptions) -> Self {
+    FileGc {
+      store,
+      options,
+      storage: None,
+    }
+  }
+
+  /// Forgets the cached quota usage of the teams whose artifacts `storage` serves
+  /// once they are removed, so that the next upload relists them.
+  pub fn with_storage(mut self, storage: Arc<StorageStore>) -> Self {
+    self.storage = Some(storage);
+    self
+  }
+
+  /// Builds the collector for `config`, `None` when nothing is stored on disk
+  /// (neither the `File` provider nor a `file` cache tier) or no limit is configured.
+  pub fn from_config(config: &Config, dry_run: bool) -> Result<Option<Self>, Error> {
+    let on_disk = matches!(config.storage_provider, StorageProvider::File)
+      || config.cache_tier == Some(CacheTier::File);
+    if !on_disk || (config.gc_max_age.is_none() && config.gc_max_size.is_none()) {
+      return Ok(None);
+    }
+    let store = get_local_file_system(&config.bucket_name, &config.fs_cache_path)?;
+    let options = GcOptions {
+      max_age: config.gc_max_age,
+      max_size: config.gc_max_size,
+      dry_run: dry_run || config.gc_dry_run,
+      interval: config.gc_interval,
+    };
+    Ok(Some(FileGc::new(store, options)))
+  }
+
+  // Last time the artifact file was read or written, falls back to the modification
+  // time when the file system doesn't track access times.
+  fn accessed(&self, location: &Path, modified: SystemTime) -> SystemTime {
+    self
+      .store
+      .path_to_filesystem(location)
+      .ok()
+      .and_then(|path| std::fs::metadata(path).ok())
+      .and_then(|metadata| metadata.accessed().ok())
+      .map_or(modified, |accessed| accessed.max(modified))
+  }
+
+  async fn entries(&self) -> Result<Vec<Entry>, Error> {
+    let mut entries: HashMap<String, Entry> = HashMap::new();
+    let mut objects = self.store.list(None);
+    while let Some(meta) = objects.try_next().await? {
+      let location = meta.location.to_string();
+      let key = location
+        .strip_suffix(METADATA_SUFFIX)
+        .unwrap_or(&location)
+        .to_string();
+      let accessed = self.accessed(&meta.location, meta.last_modified.into());
+      let entry = entries.entry(key.clone()).or_insert_with(|| Entry {
+        key,
+        locations: vec![],
+        size: 0,
+        accessed,
+      });
+      entry.locations.push(meta.location);
+      entry.size += meta.size as u64;
+      entry.accessed = entry.accessed.max(accessed);
+    }
+    Ok(entries.into_values().collect())
+  }
+
+  /// Runs a single collection and reports what was (or would be, in dry-run mode) removed.
+  pub async fn run(&self) -> Result<GcReport, Error> {
+    let mut entries = self.entries().await?;
+    entries.sort_by_key(|entry| entry.accessed);
+    let now = SystemTime::now();
+    let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let mut report = GcReport::default();
+    for entry in entries {
+      let age = now.duration_since(entry.accessed).unwrap_or_default();
+      let expired = self.options.max_age.is_some_and(|max_age| age > max_age);
+      let oversized = self
+        .options
+        .max_size
+        .is_some_and(|max_size| total > max_size);
+      if !expired && !oversized {
+        report.kept += 1;
+        report.kept_bytes += entry.size;
+        continue;
+      }
+      total -= entry.size;
+      report.removed += 1;
+      report.reclaimed_bytes += entry.size;
+      for location in entry.locations {
+        if self.options.dry_run {
+          info!("Would remove {} (last accessed {:?} ago)", location, age);
+          continue;
+        }
+        match self.store.delete(&location).await {
+          Ok(()) | Err(object_store::Error::NotFound { .. }) => {
+            info!("Removed {} (last accessed {:?} ago)", location, age)
+          }
+          Err(e) => warn!("Failed to remove {}: {}", location, e),
+        }
+      }
+      if let Some(storage) = self.storage.as_ref().filter(|_| !self.options.dry_run) {
+        storage.forget_usage(&entry.key);
+      }
+    }
+    Ok(report)
+  }
+
+  /// Runs the collector every `interval` in the background.
+  pub fn spawn(self) {
+    actix_web::rt::spawn(async move {
+      let mut interval = actix_web::rt::time::interval(self.options.interval);
+      loop {
+        interval.tick().await;
+        match self.run().await {
+          Ok(report) => info!("File cache garbage collection: {}", report),
+          Err(e) => error!("File cache garbage collection failed: {}", e),
+        }
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod gc_tests {
+  use super::*;
+  use object_store::PutPayload;
+
+  fn store(name: &str) -> Arc<LocalFileSystem> {
+    let path = std::env::temp_dir().join(format!("turbo-remote-cache-gc-{}", name));
+    let _ = std::fs::remove_dir_all(&path);
+    get_local_file_system("cache", path.to_str().unwrap()).unwrap()
+  }
+
+  // Writes `location` as if it was last read and written `ago`.
+  async fn put(store: &LocalFileSystem, location: &str, size: usize, ago: Duration) {
+    let location = Path::from(location);
+    store
+      .put(&location, PutPayload::from(vec![0u8; size]))
+      .await
+      .unwrap();
+    let time = SystemTime::now() - ago;
+    let times = std::fs::FileTimes::new()
+      .set_accessed(time)
+      .set_modified(time);
+    let path = store.path_to_filesystem(&location).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    file.set_times(times).unwrap();
+  }
+
+  #[actix_web::test]
+  async fn test_gc_max_size() {
+    let store = store("max-size");
+    let hour = Duration::from_secs(3600);
+    put(&store, "team/old", 10, hour).await;
+    put(&store, "team/old.meta", 2, hour).await;
+    put(&store, "team/new", 10, Duration::ZERO).await;
+    let gc = FileGc::new(
+      store.clone(),
+      GcOptions {
+        max_size: Some(15),
+        ..Default::default()
+      },
+    );
+    let report = gc.run().await.unwrap();
+    assert_eq!(report.removed, 1);
+    assert_eq!(report.reclaimed_bytes, 12);
+    assert!(store.head(&Path::from("team/old")).await.is_err());
+    assert!(store.head(&Path::from("team/old.meta")).await.is_err());
+    assert!(store.head(&Path::from("team/new")).await.is_ok());
+  }
+
+  #[actix_web::test]
+  async fn test_gc_max_age_dry_run() {
+    let store = store("max-age");
+    put(&store, "team/artifact", 10, Duration::from_secs(3600)).await;
+    put(&store, "team/recent", 10, Duration::ZERO).await;
+    let gc = FileGc::new(
+      store.clone(),
+      GcOptions {
+        max_age: Some(Duration::from_secs(600)),
+        dry_run: true,
+        ..Default::default()
+      },
+    );
+    let report = gc.run().await.unwrap();
+    assert_eq!(report.removed, 1);
+    assert!(store.head(&Path::from("team/artifact")).await.is_ok());
+  }
+
+  #[actix_web::test]
+  async fn test_gc_forgets_quota_usage() {
+    let dir = std::env::temp_dir().join("turbo-remote-cache-gc-quota");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config::default()
+      .with_storage_provider(StorageProvider::File)
+      .with_fs_cache_path(dir.to_str().unwrap().to_string())
+      .with_quotas(vec!["team:100".parse().unwrap()], false);
+    let storage = Arc::new(StorageStore::new(&config).unwrap());
+    let body = futures_util::stream::iter(vec![Ok(actix_web::web::Bytes::from_static(b"aaaa"))]);
+    storage
+      .put_stream("team/a", body, &Default::default())
+      .await
+      .unwrap();
+    assert_eq!(storage.usage("team").await.unwrap().objects, 1);
+
+    let gc = FileGc::from_config(&config.with_gc_max_size(Some(0)), false)
+      .unwrap()
+      .unwrap()
+      .with_storage(storage.clone());
+    assert_eq!(gc.run().await.unwrap().removed, 1);
+    assert_eq!(storage.usage("team").await.unwrap().objects, 0);
+  }
+}
@@ -0,0 +1,166 @@
+use std::{fmt::Display, ops::Range, sync::Arc, time::Instant};
+
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::{stream::BoxStream, FutureExt};
+use object_store::{
+  path::Path, GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore,
+  PutMultipartOpts, PutOptions, PutPayload, PutResult, Result, UploadPart,
+};
+
+use crate::metrics::observe_storage;
+
+/// Wraps an [`ObjectStore`] to record the latency of every backend operation.
+#[derive(Debug)]
+pub struct InstrumentedStore {
+  inner: Arc<dyn ObjectStore>,
+}
+
+impl InstrumentedStore {
+  pub fn new(inner: Arc<dyn ObjectStore>) -> Self {
+    InstrumentedStore { inner }
+  }
+}
+
+impl Display for InstrumentedStore {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Instrumented({})", self.inner)
+  }
+}
+
+#[async_trait]
+impl ObjectStore for InstrumentedStore {
+  async fn put_opts(
+    &self,
+    location: &Path,
+    payload: PutPayload,
+    opts: PutOptions,
+  ) -> Result<PutResult> {
+    let started = Instant::now();
+    let result = self.inner.put_opts(location, payload, opts).await;
+    observe_storage("put", started, &result);
+    result
+  }
+
+  async fn put_multipart_opts(
+    &self,
+    location: &Path,
+    opts: PutMultipartOpts,
+  ) -> Result<Box<dyn MultipartUpload>> {
+    let started = Instant::now();
+    let result = self.inner.put_multipart_opts(location, opts).await;
+    observe_storage("put_multipart", started, &result);
+    Ok(Box::new(InstrumentedUpload { inner: result? }))
+  }
+
+  async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+    let operation = if options.head { "head" } else { "get" };
+    let started = Instant::now();
+    let result = self.inner.get_opts(location, options).await;
+    observe_storage(operation, started, &result);
+    result
+  }
+
+  async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+    let started = Instant::now();
+    let result = self.inner.get_range(location, range).await;
+    observe_storage("get_range", started, &result);
+    result
+  }
+
+  async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+    let started = Instant::now();
+    let result = self.inner.head(location).await;
+    observe_storage("head", started, &result);
+    result
+  }
+
+  async fn delete(&self, location: &Path) -> Result<()> {
+    let started = Instant::now();
+    let result = self.inner.delete(location).await;
+    observe_storage("delete", started, &result);
+    result
+  }
+
+  // forwarded so providers delete in bulk, batches have no per object latency to record
+  fn delete_stream<'a>(
+    &'a self,
+    locations: BoxStream<'a, Result<Path>>,
+  ) -> BoxStream<'a, Result<Path>> {
+    self.inner.delete_stream(locations)
+  }
+
+  fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+    self.inner.list(prefix)
+  }
+
+  fn list_with_offset(
+    &self,
+    prefix: Option<&Path>,
+    offset: &Path,
+  ) -> BoxStream<'_, Result<ObjectMeta>> {
+    self.inner.list_with_offset(prefix, offset)
+  }
+
+  async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+    let started = Instant::now();
+    let result = self.inner.list_with_delimiter(prefix).await;
+    observe_storage("list", started, &result);
+    result
+  }
+
+  async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+    let started = Instant::now();
+    let result = self.inner.copy(from, to).await;
+    observe_storage("copy", started, &result);
+    result
+  }
+
+  async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+    let started = Instant::now();
+    let result = self.inner.rename(from, to).await;
+    observe_storage("rename", started, &result);
+    result
+  }
+
+  async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+    let started = Instant::now();
+    let result = self.inner.copy_if_not_exists(from, to).await;
+    observe_storage("copy", started, &result);
+    result
+  }
+}
+
+#[derive(Debug)]
+struct InstrumentedUpload {
+  inner: Box<dyn MultipartUpload>,
+}
+
+#[async_trait]
+impl MultipartUpload for InstrumentedUpload {
+  fn put_part(&mut self, data: PutPayload) -> UploadPart {
+    let started = Instant::now();
+    self
+      .inner
+      .put_part(data)
+      .map(move |result| {
+        observe_storage("put_part", started, &result);
+        result
+      })
+      .boxed()
+  }
+
+  async fn complete(&mut self) -> Result<PutResult> {
+    let started = Instant::now();
+    let result = self.inner.complete().await;
+    observe_storage("complete_multipart", started, &result);
+    result
+  }
+
+  async fn abort(&mut self) -> Result<()> {
+    let started = Instant::now();
+    let result = self.inner.abort().await;
+    observe_storage("abort_multipart", started, &result);
+    result
+  }
+}
@@ -0,0 +1,116 @@
+use futures_util::{stream, StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+
+use super::{ArtifactMetadata, StorageStore, METADATA_SUFFIX};
+use crate::error::Error;
+
+// Maximum number of metadata sidecars read at the same time for a page.
+const MAX_CONCURRENT_READS: usize = 16;
+
+/// A page of the artifacts of a team, ordered by hash.
+#[derive(Debug, Default)]
+pub struct ArtifactPage {
+  pub artifacts: Vec<(ObjectMeta, ArtifactMetadata)>,
+  /// Hash to list the next page after, `None` on the last page.
+  pub next: Option<String>,
+}
+
+impl StorageStore {
+  /// Lists up to `limit` artifacts of `team_id` whose hash sorts after `after`,
+  /// along with their metadata.
+  pub async fn list_artifacts(
+    &self,
+    team_id: &str,
+    after: Option<&str>,
+    limit: usize,
+  ) -> Result<ArtifactPage, Error> {
+    let prefix = Path::from(team_id);
+    let listed = match after {
+      Some(after) => {
+        let offset = Path::from(format!("{}/{}", team_id, after));
+        self.object_store.list_with_offset(Some(&prefix), &offset)
+      }
+      None => self.object_store.list(Some(&prefix)),
+    };
+    let listed = listed.try_filter(|meta| {
+      let sidecar = meta.location.as_ref().ends_with(METADATA_SUFFIX);
+      futures_util::future::ready(!sidecar)
+    });
+    let mut metas: Vec<ObjectMeta> = match self.lists_in_order {
+      // one more artifact than the page tells whether there is a next one
+      true => listed.take(limit + 1).try_collect().await?,
+      // otherwise the page is only cut once every artifact is listed and sorted
+      false => {
+        let mut metas: Vec<ObjectMeta> = listed.try_collect().await?;
+        metas.sort_by(|a, b| a.location.cmp(&b.location));
+        metas
+      }
+    };
+    let next = match metas.len() > limit {
+      true => metas[limit - 1].location.filename().map(str::to_string),
+      false => None,
+    };
+    metas.truncate(limit);
+    let artifacts = stream::iter(metas)
+      .map(|meta| async move {
+        let metadata = self.get_metadata(meta.location.as_ref()).await?;
+        Ok::<_, Error>((meta, metadata))
+      })
+      .buffered(MAX_CONCURRENT_READS)
+      .try_collect()
+      .await?;
+    Ok(ArtifactPage { artifacts, next })
+  }
+}
+
+#[cfg(test)]
+mod listing_tests {
+  use actix_web::web::Bytes;
+
+  use super::*;
+  use crate::config::{Config, StorageProvider};
+
+  // Pages are the same whether the provider of `storage` lists in order or not.
+  async fn assert_list_artifacts(storage: StorageStore) {
+    for hash in ["03", "01", "02"] {
+      let body = futures_util::stream::iter(vec![Ok(Bytes::from_static(b"data"))]);
+      let metadata = ArtifactMetadata {
+        duration: Some(10),
+        ..Default::default()
+      };
+      let path = format!("team/{}", hash);
+      storage.put_stream(&path, body, &metadata).await.unwrap();
+    }
+    storage
+      .put("other/00", Bytes::from_static(b"data"))
+      .await
+      .unwrap();
+
+    let page = storage.list_artifacts("team", None, 2).await.unwrap();
+    let hashes: Vec<_> = page
+      .artifacts
+      .iter()
+      .map(|(meta, _)| meta.location.to_string())
+      .collect();
+    assert_eq!(hashes, ["team/01", "team/02"]);
+    assert_eq!(page.artifacts[0].1.duration, Some(10));
+    assert_eq!(page.next.as_deref(), Some("02"));
+
+    let page = storage.list_artifacts("team", Some("02"), 2).await.unwrap();
+    assert_eq!(page.artifacts.len(), 1);
+    assert_eq!(page.artifacts[0].0.location.as_ref(), "team/03");
+    assert_eq!(page.next, None);
+  }
+
+  #[actix_web::test]
+  async fn test_list_artifacts() {
+    let dir = std::env::temp_dir().join("turbo-remote-cache-listing");
+    let _ = std::fs::remove_dir_all(&dir);
+    let file = Config::default()
+      .with_storage_provider(StorageProvider::File)
+      .with_fs_cache_path(dir.to_str().unwrap().to_string());
+    for config in [Config::default(), file] {
+      assert_list_artifacts(StorageStore::new(&config).unwrap()).await;
+    }
+  }
+}
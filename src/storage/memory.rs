@@ -0,0 +1,379 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  fmt::Display,
+  sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use log::{debug, warn};
+use object_store::{
+  memory::InMemory, path::Path, GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta,
+  ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult, Result, UploadPart,
+};
+
+use super::METADATA_SUFFIX;
+use crate::metrics::{MEMORY_BYTES, MEMORY_EVICTIONS};
+
+// Artifacts and their metadata sidecar share an entry, so they are evicted
+// together and an artifact is never left without the metadata needed to decode it.
+fn entry_key(location: &Path) -> Path {
+  match location.as_ref().strip_suffix(METADATA_SUFFIX) {
+    Some(artifact) => Path::from(artifact),
+    None => location.clone(),
+  }
+}
+
+#[derive(Debug, Default)]
+struct LruEntry {
+  // size of each object of the entry, the artifact and its sidecar
+  sizes: HashMap<Path, usize>,
+  tick: u64,
+}
+
+/// Sizes and recency of the objects held by [`LruMemoryStore`].
+#[derive(Debug, Default)]
+struct LruIndex {
+  entries: HashMap<Path, LruEntry>,
+  // access tick -> entry key, the first entry is the least recently used one
+  order: BTreeMap<u64, Path>,
+  tick: u64,
+  size: usize,
+}
+
+impl LruIndex {
+  fn next_tick(&mut self) -> u64 {
+    self.tick += 1;
+    self.tick
+  }
+
+  fn insert(&mut self, location: &Path, size: usize) {
+    self.remove(location);
+    let key = entry_key(location);
+    let tick = self.next_tick();
+    let entry = self.entries.entry(key.clone()).or_default();
+    self.order.remove(&entry.tick);
+    entry.tick = tick;
+    entry.sizes.insert(location.clone(), size);
+    self.order.insert(tick, key);
+    self.size += size;
+  }
+
+  fn touch(&mut self, location: &Path) {
+    let key = entry_key(location);
+    let tick = self.next_tick();
+    if let Some(entry) = self.entries.get_mut(&key) {
+      self.order.remove(&entry.tick);
+      entry.tick = tick;
+      self.order.insert(tick, key);
+    }
+  }
+
+  fn remove(&mut self, location: &Path) -> Option<usize> {
+    let key = entry_key(location);
+    let entry = self.entries.get_mut(&key)?;
+    let size = entry.sizes.remove(location)?;
+    if entry.sizes.is_empty() {
+      self.order.remove(&entry.tick);
+      self.entries.remove(&key);
+    }
+    self.size -= size;
+    Some(size)
+  }
+
+  // Drops the whole entry of `location`, returns the objects it held.
+  fn remove_entry(&mut self, location: &Path) -> Vec<Path> {
+    let Some(entry) = self.entries.remove(&entry_key(location)) else {
+      return vec![];
+    };
+    self.order.remove(&entry.tick);
+    self.size -= entry.sizes.values().sum::<usize>();
+    entry.sizes.into_keys().collect()
+  }
+
+  fn size_of(&self, location: &Path) -> Option<usize> {
+    let entry = self.entries.get(&entry_key(location))?;
+    entry.sizes.get(location).copied()
+  }
+
+  // Drops least recently used entries until the index fits in `max_size`.
+  fn evict(&mut self, max_size: usize) -> Vec<Path> {
+    let mut evicted = vec![];
+    while self.size > max_size {
+      let Some((_, key)) = self.order.pop_first() else {
+        break;
+      };
+      if let Some(entry) = self.entries.remove(&key) {
+        self.size -= entry.sizes.values().sum::<usize>();
+        evicted.extend(entry.sizes.into_keys());
+      }
+    }
+    evicted
+  }
+}
+
+#[derive(Debug)]
+struct Shared {
+  inner: InMemory,
+  index: Mutex<LruIndex>,
+  max_size: usize,
+}
+
+impl Shared {
+  fn index(&self) -> std::sync::MutexGuard<'_, LruIndex> {
+    self.index.lock().unwrap_or_else(|e| e.into_inner())
+  }
+
+  async fn record(&self, location: &Path, size: usize) {
+    let evicted = {
+      let mut index = self.index();
+      let evicted = if size > self.max_size {
+        // never evict other objects for one that can't fit anyway
+        warn!(
+          "{} ({} bytes) doesn't fit in the {} bytes memory store",
+          location, size, self.max_size
+        );
+        let mut evicted = index.remove_entry(location);
+        if !evicted.contains(location) {
+          evicted.push(location.clone());
+        }
+        evicted
+      } else {
+        index.insert(location, size);
+        index.evict(self.max_size)
+      };
+      MEMORY_BYTES.set(index.size as i64);
+      evicted
+    };
+    for path in evicted {
+      debug!("Evicting {} from the memory store", path);
+      MEMORY_EVICTIONS.inc();
+      match self.inner.delete(&path).await {
+        Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+        Err(e) => warn!("Failed to evict {}: {}", path, e),
+      }
+    }
+  }
+
+  fn forget(&self, location: &Path) {
+    let mut index = self.index();
+    index.remove(location);
+    MEMORY_BYTES.set(index.size as i64);
+  }
+}
+
+/// An in-memory store bounded to `max_size` bytes, evicting the least recently
+/// read or written objects first.
+#[derive(Debug)]
+pub struct LruMemoryStore {
+  shared: Arc<Shared>,
+}
+
+impl LruMemoryStore {
+  pub fn new(max_size: usize) -> Self {
+    LruMemoryStore {
+      shared: Arc::new(Shared {
+        inner: InMemory::new(),
+        index: Mutex::new(LruIndex::default()),
+        max_size,
+      }),
+    }
+  }
+
+  /// Total size in bytes of the objects currently held.
+  pub fn size(&self) -> usize {
+    self.shared.index().size
+  }
+}
+
+impl Display for LruMemoryStore {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "LruMemoryStore({} bytes)", self.shared.max_size)
+  }
+}
+
+#[async_trait]
+impl ObjectStore for LruMemoryStore {
+  async fn put_opts(
+    &self,
+    location: &Path,
+    payload: PutPayload,
+    opts: PutOptions,
+  ) -> Result<PutResult> {
+    let size = payload.content_length();
+    let result = self.shared.inner.put_opts(location, payload, opts).await?;
+    self.shared.record(location, size).await;
+    Ok(result)
+  }
+
+  async fn put_multipart_opts(
+    &self,
+    location: &Path,
+    opts: PutMultipartOpts,
+  ) -> Result<Box<dyn MultipartUpload>> {
+    let upload = self.shared.inner.put_multipart_opts(location, opts).await?;
+    Ok(Box::new(LruUpload {
+      inner: upload,
+      shared: self.shared.clone(),
+      location: location.clone(),
+      size: 0,
+    }))
+  }
+
+  async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+    let head = options.head;
+    let result = self.shared.inner.get_opts(location, options).await?;
+    if !head {
+      self.shared.index().touch(location);
+    }
+    Ok(result)
+  }
+
+  async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+    self.shared.inner.head(location).await
+  }
+
+  async fn delete(&self, location: &Path) -> Result<()> {
+    self.shared.inner.delete(location).await?;
+    self.shared.forget(location);
+    Ok(())
+  }
+
+  fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+    self.shared.inner.list(prefix)
+  }
+
+  fn list_with_offset(
+    &self,
+    prefix: Option<&Path>,
+    offset: &Path,
+  ) -> BoxStream<'_, Result<ObjectMeta>> {
+    self.shared.inner.list_with_offset(prefix, offset)
+  }
+
+  async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+    self.shared.inner.list_with_delimiter(prefix).await
+  }
+
+  async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+    self.shared.inner.copy(from, to).await?;
+    let size = self.shared.index().size_of(from).unwrap_or_default();
+    self.shared.record(to, size).await;
+    Ok(())
+  }
+
+  async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+    self.shared.inner.copy_if_not_exists(from, to).await?;
+    let size = self.shared.index().size_of(from).unwrap_or_default();
+    self.shared.record(to, size).await;
+    Ok(())
+  }
+}
+
+#[derive(Debug)]
+struct LruUpload {
+  inner: Box<dyn MultipartUpload>,
+  shared: Arc<Shared>,
+  location: Path,
+  size: usize,
+}
+
+#[async_trait]
+impl MultipartUpload for LruUpload {
+  fn put_part(&mut self, data: PutPayload) -> UploadPart {
+    self.size += data.content_length();
+    self.inner.put_part(data)
+  }
+
+  async fn complete(&mut self) -> Result<PutResult> {
+    let result = self.inner.complete().await?;
+    self.shared.record(&self.location, self.size).await;
+    Ok(result)
+  }
+
+  async fn abort(&mut self) -> Result<()> {
+    self.inner.abort().await
+  }
+}
+
+#[cfg(test)]
+mod memory_tests {
+  use super::*;
+
+  async fn put(store: &LruMemoryStore, location: &str, size: usize) {
+    store
+      .put(&Path::from(location), PutPayload::from(vec![0u8; size]))
+      .await
+      .unwrap();
+  }
+
+  async fn exists(store: &LruMemoryStore, location: &str) -> bool {
+    store.head(&Path::from(location)).await.is_ok()
+  }
+
+  #[actix_web::test]
+  async fn test_evicts_least_recently_used() {
+    let store = LruMemoryStore::new(10);
+    put(&store, "a", 4).await;
+    put(&store, "b", 4).await;
+    // reading `a` makes `b` the least recently used object
+    store.get(&Path::from("a")).await.unwrap();
+    put(&store, "c", 4).await;
+    assert!(exists(&store, "a").await);
+    assert!(!exists(&store, "b").await);
+    assert!(exists(&store, "c").await);
+    assert_eq!(store.size(), 8);
+  }
+
+  #[actix_web::test]
+  async fn test_overwrite_and_delete_update_size() {
+    let store = LruMemoryStore::new(10);
+    put(&store, "a", 4).await;
+    put(&store, "a", 6).await;
+    assert_eq!(store.size(), 6);
+    store.delete(&Path::from("a")).await.unwrap();
+    assert_eq!(store.size(), 0);
+  }
+
+  #[actix_web::test]
+  async fn test_multipart_upload_is_tracked() {
+    let store = LruMemoryStore::new(10);
+    put(&store, "a", 6).await;
+    let mut upload = store.put_multipart(&Path::from("b")).await.unwrap();
+    upload.put_part(vec![0u8; 3].into()).await.unwrap();
+    upload.put_part(vec![0u8; 3].into()).await.unwrap();
+    upload.complete().await.unwrap();
+    assert!(!exists(&store, "a").await);
+    assert!(exists(&store, "b").await);
+    assert_eq!(store.size(), 6);
+  }
+
+  #[actix_web::test]
+  async fn test_evicts_artifact_with_its_metadata() {
+    let store = LruMemoryStore::new(10);
+    put(&store, "team/a", 4).await;
+    put(&store, "team/b", 4).await;
+    put(&store, "team/a.meta", 1).await;
+    // writing the sidecar made `team/a` recent again, `team/b` goes first
+    put(&store, "team/c", 2).await;
+    assert!(!exists(&store, "team/b").await);
+    assert!(exists(&store, "team/a").await);
+    assert_eq!(store.size(), 7);
+
+    put(&store, "team/d", 5).await;
+    assert!(!exists(&store, "team/a").await);
+    assert!(!exists(&store, "team/a.meta").await);
+    store.delete(&Path::from("team/d")).await.unwrap();
+    assert_eq!(store.size(), 2);
+  }
+
+  #[actix_web::test]
+  async fn test_object_larger_than_budget() {
+    let store = LruMemoryStore::new(10);
+    put(&store, "a", 4).await;
+    put(&store, "b", 11).await;
+    assert!(exists(&store, "a").await);
+    assert!(!exists(&store, "b").await);
+    assert_eq!(store.size(), 4);
+  }
+}
@@ -0,0 +1,162 @@
+use std::fmt::Display;
+
+use futures_util::TryStreamExt;
+use log::info;
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+
+use super::{metadata_path, tiered::copy_object, StorageStore, METADATA_SUFFIX, QUARANTINE_PREFIX};
+use crate::error::Error;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MigrateReport {
+  pub copied: usize,
+  pub copied_bytes: u64,
+  pub skipped: usize,
+}
+
+impl Display for MigrateReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "copied {} objects ({} bytes), skipped {} already migrated",
+      self.copied, self.copied_bytes, self.skipped
+    )
+  }
+}
+
+// Whether `meta` was already copied to `to`: a copy of the same size written after it.
+async fn migrated(to: &StorageStore, meta: &ObjectMeta) -> Result<bool, Error> {
+  match to.object_store.head(&meta.location).await {
+    Ok(existing) => Ok(existing.size == meta.size && existing.last_modified >= meta.last_modified),
+    Err(object_store::Error::NotFound { .. }) => Ok(false),
+    Err(e) => Err(e.into()),
+  }
+}
+
+impl StorageStore {
+  /// Copies every object of this store into `to` as it is stored, so compressed
+  /// or encrypted artifacts keep their metadata and `to` must use the same keys.
+  /// Artifacts are copied along with their metadata, both are skipped when already
+  /// copied since they were written so an interrupted migration can be resumed.
+  /// Quarantined artifacts are left behind.
+  pub async fn migrate_to(&self, to: &StorageStore, dry_run: bool) -> Result<MigrateReport, Error> {
+    let quarantine = Path::from(QUARANTINE_PREFIX);
+    let mut report = MigrateReport::default();
+    let mut objects = self.object_store.list(None);
+    while let Some(meta) = objects.try_next().await? {
+      let sidecar = meta.location.as_ref().ends_with(METADATA_SUFFIX);
+      if sidecar || meta.location.prefix_matches(&quarantine) {
+        continue;
+      }
+      let metadata = match self
+        .object_store
+        .head(&metadata_path(meta.location.as_ref()))
+        .await
+      {
+        Ok(metadata) => Some(metadata),
+        Err(object_store::Error::NotFound { .. }) => None,
+        Err(e) => return Err(e.into()),
+      };
+      let objects = [Some(meta), metadata]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+      let mut up_to_date = true;
+      for meta in &objects {
+        if !migrated(to, meta).await? {
+          up_to_date = false;
+          break;
+        }
+      }
+      if up_to_date {
+        report.skipped += objects.len();
+        continue;
+      }
+      for meta in objects {
+        if dry_run {
+          info!("Would copy {} ({} bytes)", meta.location, meta.size);
+        } else {
+          copy_object(
+            self.object_store.as_ref(),
+            to.object_store.as_ref(),
+            &meta.location,
+          )
+          .await?;
+          info!("Copied {} ({} bytes)", meta.location, meta.size);
+        }
+        report.copied += 1;
+        report.copied_bytes += meta.size as u64;
+      }
+    }
+    Ok(report)
+  }
+}
+
+#[cfg(test)]
+mod migrate_tests {
+  use actix_web::web::Bytes;
+
+  use super::*;
+  use crate::{config::Config, storage::ArtifactMetadata};
+
+  #[actix_web::test]
+  async fn test_migrate() {
+    let from = StorageStore::new(&Config::default()).unwrap();
+    let to = StorageStore::new(&Config::default()).unwrap();
+    from
+      .put("team/a", Bytes::from_static(b"aaaa"))
+      .await
+      .unwrap();
+    from.put("team/b", Bytes::from_static(b"bb")).await.unwrap();
+    from
+      .put(".quarantine/team/c", Bytes::from_static(b"c"))
+      .await
+      .unwrap();
+    to.put("team/b", Bytes::from_static(b"bb")).await.unwrap();
+
+    let report = from.migrate_to(&to, true).await.unwrap();
+    assert_eq!(report.copied, 1);
+    assert!(!to.exists("team/a").await.unwrap());
+
+    let report = from.migrate_to(&to, false).await.unwrap();
+    assert_eq!(
+      report,
+      MigrateReport {
+        copied: 1,
+        copied_bytes: 4,
+        skipped: 1,
+      }
+    );
+    assert!(to.exists("team/a").await.unwrap());
+    assert!(!to.exists(".quarantine/team/c").await.unwrap());
+  }
+
+  #[actix_web::test]
+  async fn test_migrate_overwritten_artifact() {
+    let from = StorageStore::new(&Config::default()).unwrap();
+    let to = StorageStore::new(&Config::default()).unwrap();
+    let put = |data: &'static [u8], duration| {
+      let body = futures_util::stream::iter(vec![Ok(Bytes::from_static(data))]);
+      let metadata = ArtifactMetadata {
+        duration: Some(duration),
+        ..Default::default()
+      };
+      let from = &from;
+      async move { from.put_stream("team/a", body, &metadata).await.unwrap() }
+    };
+    put(b"aaaa", 1).await;
+    let report = from.migrate_to(&to, false).await.unwrap();
+    assert_eq!(report.copied, 2);
+    let report = from.migrate_to(&to, false).await.unwrap();
+    assert_eq!((report.copied, report.skipped), (0, 2));
+
+    // same size, the artifact and its metadata are copied again
+    put(b"bbbb", 2).await;
+    let report = from.migrate_to(&to, false).await.unwrap();
+    assert_eq!((report.copied, report.skipped), (2, 0));
+    let (artifact, metadata) = to.get("team/a").await.unwrap();
+    let data: Vec<Bytes> = artifact.stream.try_collect().await.unwrap();
+    assert_eq!(data.concat(), b"bbbb");
+    assert_eq!(metadata.duration, Some(2));
+  }
+}
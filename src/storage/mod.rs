@@ -0,0 +1,841 @@
+mod checksum;
+mod codec;
+pub mod crypto;
+pub mod gc;
+mod instrumented;
+pub mod listing;
+mod memory;
+pub mod migrate;
+pub mod quota;
+pub mod retention;
+mod tiered;
+
+use crate::config::{CacheTier, Compression, Config, StorageProvider};
+use crate::error::Error;
+use crate::metrics::CORRUPTED_ARTIFACTS;
+use actix_web::web::Bytes;
+use futures_util::future::try_join;
+use futures_util::stream::{self, BoxStream, LocalBoxStream};
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use http::Method;
+use log::{debug, error, warn};
+use object_store::{
+  aws::{AmazonS3, AmazonS3Builder},
+  azure::{MicrosoftAzure, MicrosoftAzureBuilder},
+  gcp::{GoogleCloudStorage, GoogleCloudStorageBuilder},
+  local::LocalFileSystem,
+  path::Path,
+  signer::Signer,
+  GetOptions, ObjectMeta, ObjectStore,
+};
+use object_store::{PutPayload, WriteMultipart};
+use serde::{Deserialize, Serialize};
+use std::{
+  cell::{Cell, RefCell},
+  fs::create_dir_all,
+  ops::Range,
+  sync::Arc,
+  time::{Duration, Instant, SystemTime},
+};
+
+use self::checksum::{verify_sha256, Sha256};
+use self::codec::{is_zstd, zstd_decode, zstd_encode, Encoding};
+use self::crypto::{Encryption, Keyring};
+use self::quota::Quotas;
+use self::{instrumented::InstrumentedStore, memory::LruMemoryStore, tiered::TieredStore};
+
+// Maximum number of multipart chunks being uploaded concurrently for a single artifact.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+// Time an artifact is given after being written for its metadata to be written too,
+// artifacts failing verification within it are served as misses but not quarantined.
+const OVERWRITE_WINDOW: Duration = Duration::from_secs(60);
+
+// Prefix corrupted artifacts are moved under to be inspected.
+const QUARANTINE_PREFIX: &str = ".quarantine";
+
+// Object written and removed again to check the storage provider is reachable.
+const CHECK_PATH: &str = ".turbo-remote-cache-check";
+
+// Suffix of the sidecar object holding the metadata of an artifact.
+const METADATA_SUFFIX: &str = ".meta";
+
+/// Turborepo metadata sent along an artifact and returned when it is read back.
+///
+/// It is kept in a JSON sidecar object next to the artifact rather than in object
+/// attributes, since not every provider (e.g. `File`) supports them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArtifactMetadata {
+  /// Time in milliseconds it took to produce the artifact (`x-artifact-duration`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub duration: Option<u64>,
+  /// Signature of the artifact when remote cache signing is on (`x-artifact-tag`).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub tag: Option<String>,
+  /// Encoding of the stored object, `None` when it is stored as received.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub encoding: Option<Encoding>,
+  /// Key and data key the stored object is encrypted with, `None` when it's stored in clear.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub encryption: Option<Encryption>,
+  /// Hex encoded SHA-256 of the artifact as received, verified when it is read.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sha256: Option<String>,
+  /// Size in bytes of the artifact as received, set when it is stored encoded or encrypted.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub size: Option<u64>,
+  /// Size in bytes of the stored object, which no longer matches once the artifact
+  /// is overwritten and its metadata is stale.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub stored_size: Option<u64>,
+}
+
+impl ArtifactMetadata {
+  /// Whether the stored object holds the artifact bytes as they were received.
+  pub fn stored_as_received(&self) -> bool {
+    self.encoding.is_none() && self.encryption.is_none()
+  }
+
+  /// Size in bytes of the artifact as sent to clients.
+  pub fn content_length(&self, meta: &ObjectMeta) -> u64 {
+    self.size.unwrap_or(meta.size as u64)
+  }
+}
+
+/// A stored artifact, decoded while it is streamed.
+pub struct ArtifactBody {
+  pub meta: ObjectMeta,
+  /// Size in bytes of the decoded artifact.
+  pub size: u64,
+  pub stream: BoxStream<'static, Result<Bytes, Error>>,
+}
+
+// Whether the artifact at `path` is still the object `meta` described by `metadata`
+// and was written long enough ago for its metadata to be stored too. A read racing
+// an overwrite fails its verification without anything being corrupted.
+async fn settled(
+  store: &dyn ObjectStore,
+  path: &str,
+  meta: &ObjectMeta,
+  metadata: &ArtifactMetadata,
+) -> bool {
+  let Ok(current) = store.head(&Path::from(path)).await else {
+    return false;
+  };
+  let age = SystemTime::now()
+    .duration_since(current.last_modified.into())
+    .unwrap_or_default();
+  current.e_tag == meta.e_tag
+    && current.last_modified == meta.last_modified
+    && age > OVERWRITE_WINDOW
+    && matches!(read_metadata(store, path).await, Ok(current) if current == *metadata)
+}
+
+// Moves a corrupted artifact and its metadata out of the way, so it's rebuilt
+// and uploaded again by the next client missing it.
+async fn quarantine(store: &dyn ObjectStore, path: &str) {
+  for (from, to) in [
+    (
+      Path::from(path),
+      Path::from(format!("{}/{}", QUARANTINE_PREFIX, path)),
+    ),
+    (
+      metadata_path(path),
+      metadata_path(&format!("{}/{}", QUARANTINE_PREFIX, path)),
+    ),
+  ] {
+    match store.rename(&from, &to).await {
+      Ok(()) => warn!("Quarantined corrupted {} to {}", from, to),
+      Err(object_store::Error::NotFound { .. }) => {}
+      Err(e) => error!("Failed to quarantine corrupted {}: {}", from, e),
+    }
+  }
+}
+
+// Reads the first `limit` bytes of `stream` so errors in them are returned before
+// the stream is handed to a client.
+async fn verify_prefix(
+  stream: BoxStream<'static, Result<Bytes, Error>>,
+  limit: usize,
+) -> Result<BoxStream<'static, Result<Bytes, Error>>, Error> {
+  // the rest of the stream is polled again even once it ended
+  let mut stream = stream.fuse();
+  let mut prefix = vec![];
+  let mut read = 0;
+  while read < limit {
+    match stream.next().await {
+      Some(chunk) => {
+        let chunk = chunk?;
+        read += chunk.len();
+        prefix.push(Ok(chunk));
+      }
+      None => break,
+    }
+  }
+  Ok(stream::iter(prefix).chain(stream).boxed())
+}
+
+fn metadata_path(path: &str) -> Path {
+  Path::from(format!("{}{}", path, METADATA_SUFFIX))
+}
+
+// Artifacts stored without a sidecar (e.g. before metadata was supported) get empty metadata.
+async fn read_metadata(store: &dyn ObjectStore, path: &str) -> Result<ArtifactMetadata, Error> {
+  let data = match store.get(&metadata_path(path)).await {
+    Ok(result) => result.bytes().await?,
+    Err(object_store::Error::NotFound { .. }) => return Ok(ArtifactMetadata::default()),
+    Err(e) => return Err(e.into()),
+  };
+  Ok(serde_json::from_slice(&data).unwrap_or_else(|e| {
+    warn!("Ignoring invalid metadata for {}: {}", path, e);
+    ArtifactMetadata::default()
+  }))
+}
+
+pub struct StorageStore {
+  object_store: Arc<dyn ObjectStore>,
+  // zstd level artifacts are compressed with, `None` stores them as received
+  compression_level: Option<i32>,
+  // keys artifacts are encrypted with, `None` stores them in clear
+  keyring: Option<Keyring>,
+  // signer of the provider when clients are redirected to presigned URLs
+  signer: Option<Arc<dyn Signer>>,
+  presigned_url_expiry: Duration,
+  // whether the provider lists objects in order, so listings can stop early
+  lists_in_order: bool,
+  // decoded bytes read before answering a GET of a checksummed or encrypted artifact,
+  // artifacts up to this size are fully verified before anything is sent so corruption
+  // surfaces as a miss, larger ones fail mid-transfer after a `200`
+  verified_size: usize,
+  // storage quotas of the teams, `None` when no quota is configured
+  quotas: Option<Quotas>,
+}
+
+// A store able to presign URLs, along with its signer.
+type SigningStore = (Arc<dyn ObjectStore>, Option<Arc<dyn Signer>>);
+
+fn signing<T: ObjectStore + Signer>(store: Arc<T>) -> SigningStore {
+  (store.clone(), Some(store))
+}
+
+fn get_gcs_store(bucket_name: &str) -> Result<Arc<GoogleCloudStorage>, Error> {
+  let gcs = GoogleCloudStorageBuilder::from_env()
+    .with_bucket_name(bucket_name)
+    .build()?;
+  Ok(Arc::new(gcs))
+}
+
+fn get_azure_store(bucket_name: &str) -> Result<Arc<MicrosoftAzure>, Error> {
+  let azure = MicrosoftAzureBuilder::from_env()
+    .with_container_name(bucket_name)
+    .build()?;
+
+  Ok(Arc::new(azure))
+}
+
+fn get_s3_store(bucket_name: &str) -> Result<Arc<AmazonS3>, Error> {
+  let s3 = AmazonS3Builder::from_env()
+    .with_bucket_name(bucket_name)
+    .build()?;
+
+  Ok(Arc::new(s3))
+}
+
+fn get_local_file_system(
+  bucket_name: &str,
+  fs_cache_path: &str,
+) -> Result<Arc<LocalFileSystem>, Error> {
+  let cache_path = format!("{}/{}", fs_cache_path, bucket_name);
+  // create the folder if it doesn't exist
+  create_dir_all(&cache_path)
+    .map_err(|e| Error::Internal(format!("error creating cache folder {}: {}", cache_path, e)))?;
+  let local = LocalFileSystem::new_with_prefix(cache_path)?;
+  Ok(Arc::new(local))
+}
+
+fn get_file_store(bucket_name: &str, fs_cache_path: &str) -> Result<Arc<dyn ObjectStore>, Error> {
+  Ok(get_local_file_system(bucket_name, fs_cache_path)?)
+}
+
+fn get_memory_store(max_size: usize) -> Result<Arc<dyn ObjectStore>, Error> {
+  Ok(Arc::new(LruMemoryStore::new(max_size)))
+}
+
+// Only cloud providers can presign URLs, `File` and `Memory` have no signer.
+fn get_provider_store(config: &Config) -> Result<SigningStore, Error> {
+  let bucket_name = config.bucket_name.as_str();
+  match config.storage_provider {
+    StorageProvider::Memory => Ok((get_memory_store(config.memory_max_size)?, None)),
+    StorageProvider::S3 => Ok(signing(get_s3_store(bucket_name)?)),
+    StorageProvider::Azure => Ok(signing(get_azure_store(bucket_name)?)),
+    StorageProvider::Gcs => Ok(signing(get_gcs_store(bucket_name)?)),
+    StorageProvider::File => Ok((get_file_store(bucket_name, &config.fs_cache_path)?, None)),
+  }
+}
+
+fn get_object_store(config: &Config) -> Result<SigningStore, Error> {
+  let (remote, signer) = get_provider_store(config)?;
+  let Some(tier) = config.cache_tier else {
+    return Ok((remote, signer));
+  };
+  if matches!(
+    config.storage_provider,
+    StorageProvider::Memory | StorageProvider::File
+  ) {
+    warn!(
+      "Ignoring the {} cache tier, the {} storage provider is already local",
+      tier, config.storage_provider
+    );
+    return Ok((remote, signer));
+  }
+  let local = match tier {
+    CacheTier::Memory => get_memory_store(config.memory_max_size)?,
+    CacheTier::File => get_file_store(&config.bucket_name, &config.fs_cache_path)?,
+  };
+  let tiered = TieredStore::new(local, remote, config.cache_write_policy);
+  Ok((Arc::new(tiered), signer))
+}
+
+/// Checks the storage provider of `config` can be written, read and deleted from.
+/// The provider is reached directly, a write-back cache tier would accept the
+/// write while the provider is unreachable.
+pub async fn check(config: &Config) -> Result<(), Error> {
+  let (remote, _) = get_provider_store(config)?;
+  let path = Path::from(CHECK_PATH);
+  let data = Bytes::from_static(b"turbo-remote-cache");
+  remote.put(&path, PutPayload::from(data.clone())).await?;
+  let read = remote.get(&path).await?.bytes().await?;
+  remote.delete(&path).await?;
+  if read != data {
+    return Err(Error::Internal(format!(
+      "{} was read back with different content",
+      CHECK_PATH
+    )));
+  }
+  Ok(())
+}
+
+impl StorageStore {
+  pub fn new(config: &Config) -> Result<Self, Error> {
+    // create an ObjectStore
+    let (object_store, signer) = get_object_store(config)?;
+    debug!("Using storage provider: {:?}", object_store);
+    let object_store = Arc::new(InstrumentedStore::new(object_store));
+    let compression_level = match config.compression {
+      Compression::None => None,
+      Compression::Zstd => {
+        let levels = zstd::compression_level_range();
+        if !levels.contains(&config.compression_level) {
+          return Err(Error::Invalid(format!(
+            "COMPRESSION_LEVEL must be between {} and {}",
+            levels.start(),
+            levels.end()
+          )));
+        }
+        Some(config.compression_level)
+      }
+    };
+    let keyring = match &config.encryption_key_file {
+      Some(path) => {
+        debug!("Encrypting artifacts with the keys from {}", path);
+        Some(Keyring::from_file(path)?)
+      }
+      None => None,
+    };
+    let signer = match (config.presigned_urls, signer) {
+      (false, _) => None,
+      (true, None) => {
+        warn!(
+          "Proxying artifacts, the {} storage provider can't presign URLs",
+          config.storage_provider
+        );
+        None
+      }
+      // the bucket would serve and receive artifacts as stored, bypassing the codecs
+      (true, Some(_)) if compression_level.is_some() || keyring.is_some() => {
+        warn!("Proxying artifacts, presigned URLs can't be used with compression or encryption");
+        None
+      }
+      (true, signer) => signer,
+    };
+    Ok(StorageStore {
+      object_store,
+      compression_level,
+      keyring,
+      signer,
+      presigned_url_expiry: config.presigned_url_expiry,
+      lists_in_order: config.storage_provider.lists_in_order(),
+      verified_size: config.verified_size,
+      quotas: Quotas::new(&config.quotas, config.quota_evict),
+    })
+  }
+
+  /// Returns a presigned URL to download the artifact at `path` straight from the
+  /// bucket, or `None` when it must be proxied: presigned URLs are off, or the
+  /// artifact has a tag that only the server's response headers could carry.
+  pub async fn download_url(&self, path: &str) -> Result<Option<String>, Error> {
+    let Some(signer) = &self.signer else {
+      return Ok(None);
+    };
+    let (_, metadata) = self.head(path).await?;
+    if metadata.tag.is_some() || !metadata.stored_as_received() {
+      return Ok(None);
+    }
+    let url = signer
+      .signed_url(Method::GET, &Path::from(path), self.presigned_url_expiry)
+      .await?;
+    Ok(Some(url.to_string()))
+  }
+
+  pub async fn put(&self, path: &str, data: Bytes) -> Result<(), Error> {
+    let payload = PutPayload::from(data);
+    self.object_store.put(&Path::from(path), payload).await?;
+    Ok(())
+  }
+
+  /// Streams `stream` into the object at `path` using a multipart upload, so the
+  /// artifact is never held in memory as a whole. The upload is aborted if the
+  /// stream yields an error or the stored bytes go over the quota of the team,
+  /// otherwise `metadata` is stored once the object is complete.
+  ///
+  /// Artifacts are compressed on the way when compression is on, unless they
+  /// already are zstd streams, then encrypted when a key file is configured.
+  pub async fn put_stream<S>(
+    &self,
+    path: &str,
+    mut stream: S,
+    metadata: &ArtifactMetadata,
+  ) -> Result<(), Error>
+  where
+    S: Stream<Item = Result<Bytes, Error>> + Unpin,
+  {
+    let first = stream.next().await.transpose()?;
+    let encoding = match (&self.compression_level, &first) {
+      (Some(_), Some(chunk)) if !is_zstd(chunk) => Some(Encoding::Zstd),
+      _ => None,
+    };
+    let mut size = 0;
+    let mut hash = Sha256::new();
+    let body = stream::iter(first.map(Ok))
+      .chain(stream)
+      .inspect_ok(|chunk| {
+        size += chunk.len() as u64;
+        hash.update(chunk);
+      });
+    let body: LocalBoxStream<'_, Result<Bytes, Error>> = match (encoding, self.compression_level) {
+      (Some(Encoding::Zstd), Some(level)) => zstd_encode(body, level)?.boxed_local(),
+      _ => body.boxed_local(),
+    };
+    let (encryption, body) = match &self.keyring {
+      Some(keyring) => {
+        let (encryption, body) = keyring.encrypt(path, body)?;
+        (Some(encryption), body.boxed_local())
+      }
+      None => (None, body),
+    };
+    let transformed = encoding.is_some() || encryption.is_some();
+    // the quota of the team is reserved as the artifact is stored
+    let reservation = self.reserve(path).await?.map(RefCell::new);
+    let stored = Cell::new(0);
+    let body = body.and_then(|chunk| {
+      let (reservation, stored) = (&reservation, &stored);
+      async move {
+        let bytes = chunk.len() as u64;
+        if let Some(reservation) = reservation {
+          self.reserve_stored(path, reservation, bytes).await?;
+        }
+        stored.set(stored.get() + bytes);
+        Ok(chunk)
+      }
+    });
+    let written = self.write_stream(path, Box::pin(body)).await?;
+    if let Some(reservation) = reservation {
+      reservation.into_inner().record(written);
+    }
+    let stored = stored.get();
+    let metadata = ArtifactMetadata {
+      encoding,
+      encryption,
+      sha256: Some(hash.finish()),
+      size: transformed.then_some(size),
+      stored_size: Some(stored),
+      ..metadata.clone()
+    };
+    self.put_metadata(path, &metadata).await
+  }
+
+  // Returns when the upload started completing, the object can't be listed before.
+  async fn write_stream<S>(&self, path: &str, mut stream: S) -> Result<Instant, Error>
+  where
+    S: Stream<Item = Result<Bytes, Error>> + Unpin,
+  {
+    let upload = self.object_store.put_multipart(&Path::from(path)).await?;
+    let mut writer = WriteMultipart::new(upload);
+    while let Some(chunk) = stream.next().await {
+      let chunk = match chunk {
+        Ok(chunk) => chunk,
+        Err(e) => {
+          writer.abort().await?;
+          return Err(e);
+        }
+      };
+      if let Err(e) = writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await {
+        writer.abort().await?;
+        return Err(e.into());
+      }
+      writer.put(chunk);
+    }
+    let completing = Instant::now();
+    writer.finish().await?;
+    Ok(completing)
+  }
+
+  /// Returns the artifact at `path` without reading its body, callers should
+  /// stream [`ArtifactBody::stream`] to the client.
+  pub async fn get(&self, path: &str) -> Result<(ArtifactBody, ArtifactMetadata), Error> {
+    let result = async { Ok(self.object_store.get(&Path::from(path)).await?) };
+    let (result, metadata) = try_join(result, self.get_metadata(path)).await?;
+    let meta = result.meta.clone();
+    // truncated, or overwritten between reading it and its metadata
+    if let Some(stored) = metadata
+      .stored_size
+      .filter(|&stored| stored != meta.size as u64)
+    {
+      self.on_corrupted(path, &meta, &metadata)();
+      return Err(Error::Corrupted(format!(
+        "{} has {} bytes instead of {}",
+        path, meta.size, stored
+      )));
+    }
+    let size = metadata.content_length(&meta);
+    let stream = result.into_stream().map_err(Error::from).boxed();
+    let stream = match (&self.keyring, &metadata.encryption) {
+      (Some(keyring), Some(encryption)) => match keyring.decrypt(path, encryption, stream) {
+        // a segment failing authentication ends the stream, so it's only called once
+        Ok(stream) => {
+          let mut on_corrupted = Some(self.on_corrupted(path, &meta, &metadata));
+          stream
+            .inspect_err(move |e| {
+              if let (Error::Corrupted(_), Some(on_corrupted)) = (e, on_corrupted.take()) {
+                on_corrupted();
+              }
+            })
+            .boxed()
+        }
+        Err(e) => {
+          self.on_corrupted(path, &meta, &metadata)();
+          return Err(e);
+        }
+      },
+      (Some(_), None) => {
+        return Err(Error::Corrupted(format!(
+          "{} isn't encrypted while encryption is on",
+          path
+        )))
+      }
+      (None, Some(encryption)) => {
+        return Err(Error::Corrupted(format!(
+          "{} is encrypted with key {} but no key file is configured",
+          path, encryption.key_id
+        )))
+      }
+      (None, None) => stream,
+    };
+    let stream = match metadata.encoding {
+      Some(Encoding::Zstd) => zstd_decode(stream)?.boxed(),
+      None => stream,
+    };
+    let stream = match &metadata.sha256 {
+      Some(expected) => verify_sha256(
+        stream,
+        path,
+        expected,
+        self.on_corrupted(path, &meta, &metadata),
+      )
+      .boxed(),
+      None => stream,
+    };
+    let stream = if metadata.sha256.is_some() || metadata.encryption.is_some() {
+      verify_prefix(stream, self.verified_size).await?
+    } else {
+      stream
+    };
+    Ok((ArtifactBody { meta, size, stream }, metadata))
+  }
+
+  // Counts the artifact at `path` as corrupted and quarantines it, called once it
+  // failed its checksum or authentication. It's left alone unless it's `settled`,
+  // the failure may otherwise come from a read racing an overwrite.
+  fn on_corrupted(
+    &self,
+    path: &str,
+    meta: &ObjectMeta,
+    metadata: &ArtifactMetadata,
+  ) -> impl FnOnce() + Send + 'static {
+    let store = self.object_store.clone();
+    let path = path.to_string();
+    let meta = meta.clone();
+    let metadata = metadata.clone();
+    move || {
+      actix_web::rt::spawn(async move {
+        if settled(store.as_ref(), &path, &meta, &metadata).await {
+          CORRUPTED_ARTIFACTS.inc();
+          quarantine(store.as_ref(), &path).await;
+        } else {
+          debug!(
+            "Not quarantining {}, it may have been read while overwritten",
+            path
+          );
+        }
+      });
+    }
+  }
+
+  /// Returns `range` of the artifact at `path`, only the requested bytes are read
+  /// from the backend. The artifact must be [`ArtifactMetadata::stored_as_received`],
+  /// partial reads can't be verified against its checksum.
+  pub async fn get_range(&self, path: &str, range: Range<u64>) -> Result<ArtifactBody, Error> {
+    let options = GetOptions {
+      range: Some((range.start as usize..range.end as usize).into()),
+      ..Default::default()
+    };
+    let result = self
+      .object_store
+      .get_opts(&Path::from(path), options)
+      .await?;
+    Ok(ArtifactBody {
+      meta: result.meta.clone(),
+      size: range.end - range.start,
+      stream: result.into_stream().map_err(Error::from).boxed(),
+    })
+  }
+
+  pub async fn head(&self, path: &str) -> Result<(ObjectMeta, ArtifactMetadata), Error> {
+    let meta = async { Ok(self.object_store.head(&Path::from(path)).await?) };
+    try_join(meta, self.get_metadata(path)).await
+  }
+
+  async fn put_metadata(&self, path: &str, metadata: &ArtifactMetadata) -> Result<(), Error> {
+    let data = serde_json::to_vec(metadata)
+      .map_err(|e| Error::Internal(format!("error serializing metadata: {}", e)))?;
+    self
+      .object_store
+      .put(&metadata_path(path), PutPayload::from(data))
+      .await?;
+    Ok(())
+  }
+
+  async fn get_metadata(&self, path: &str) -> Result<ArtifactMetadata, Error> {
+    read_metadata(self.object_store.as_ref(), path).await
+  }
+
+  /// Deletes the artifact at `path` along with its metadata and returns it, or
+  /// `None` when it was already missing.
+  pub async fn delete(&self, path: &str) -> Result<Option<ObjectMeta>, Error> {
+    let meta = match self.object_store.head(&Path::from(path)).await {
+      Ok(meta) => Some(meta),
+      Err(object_store::Error::NotFound { .. }) => None,
+      Err(e) => return Err(e.into()),
+    };
+    self
+      .delete_objects(vec![Path::from(path), metadata_path(path)])
+      .await?;
+    self.forget_usage(path);
+    Ok(meta)
+  }
+
+  /// Deletes every artifact under `prefix` (e.g. a team) along with their metadata
+  /// and returns the deleted artifacts.
+  pub async fn delete_prefix(&self, prefix: &str) -> Result<Vec<ObjectMeta>, Error> {
+    let objects: Vec<ObjectMeta> = self
+      .object_store
+      .list(Some(&Path::from(prefix)))
+      .try_collect()
+      .await?;
+    let locations = objects.iter().map(|meta| meta.location.clone()).collect();
+    self.delete_objects(locations).await?;
+    self.forget_usage(prefix);
+    Ok(
+      objects
+        .into_iter()
+        .filter(|meta| !meta.location.as_ref().ends_with(METADATA_SUFFIX))
+        .collect(),
+    )
+  }
+
+  // Deletes `locations` in bulk where the provider supports it, missing ones are skipped.
+  async fn delete_objects(&self, locations: Vec<Path>) -> Result<(), Error> {
+    let locations = stream::iter(locations).map(Ok).boxed();
+    let mut deleted = self.object_store.delete_stream(locations);
+    while let Some(result) = deleted.next().await {
+      match result {
+        Ok(_) | Err(object_store::Error::NotFound { .. }) => {}
+        Err(e) => return Err(e.into()),
+      }
+    }
+    Ok(())
+  }
+
+  /// Lists the teams storing artifacts, i.e. the top level prefixes except the
+  /// quarantine and the check object.
+  pub async fn teams(&self) -> Result<Vec<String>, Error> {
+    let listed = self.object_store.list_with_delimiter(None).await?;
+    let teams = listed
+      .common_prefixes
+      .into_iter()
+      .map(|prefix| prefix.to_string())
+      .filter(|team| team != QUARANTINE_PREFIX)
+      .collect();
+    Ok(teams)
+  }
+
+  pub async fn exists(&self, path: &str) -> Result<bool, Error> {
+    match self.object_store.head(&Path::from(path)).await {
+      Ok(_) => Ok(true),
+      Err(object_store::Error::NotFound { .. }) => Ok(false),
+      Err(e) => Err(e.into()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod storage_tests {
+  use super::*;
+  use crate::config::{StorageProvider, DEFAULT_VERIFIED_SIZE};
+
+  fn signing_storage() -> StorageStore {
+    let s3 = AmazonS3Builder::new()
+      .with_bucket_name("cache")
+      .with_region("us-east-1")
+      .with_access_key_id("key")
+      .with_secret_access_key("secret")
+      .build()
+      .unwrap();
+    StorageStore {
+      object_store: Arc::new(LruMemoryStore::new(1024)),
+      compression_level: None,
+      keyring: None,
+      signer: Some(Arc::new(s3)),
+      presigned_url_expiry: Duration::from_secs(60),
+      lists_in_order: true,
+      verified_size: DEFAULT_VERIFIED_SIZE,
+      quotas: None,
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_presigned_urls() {
+    let storage = signing_storage();
+    assert!(matches!(
+      storage.download_url("team/123").await,
+      Err(Error::NotFound(_))
+    ));
+    storage
+      .put("team/123", Bytes::from_static(b"test"))
+      .await
+      .unwrap();
+    let url = storage.download_url("team/123").await.unwrap().unwrap();
+    assert!(url.starts_with("https://s3.us-east-1.amazonaws.com/cache/team/123?"));
+    assert!(url.contains("X-Amz-Expires=60"));
+    assert!(url.contains("X-Amz-Signature="));
+
+    // the tag is only sent in the response headers of proxied downloads
+    let metadata = ArtifactMetadata {
+      tag: Some("signature".to_string()),
+      ..Default::default()
+    };
+    storage.put_metadata("team/123", &metadata).await.unwrap();
+    assert_eq!(storage.download_url("team/123").await.unwrap(), None);
+  }
+
+  fn body(byte: u8, size: usize) -> impl Stream<Item = Result<Bytes, Error>> + Unpin {
+    stream::iter(vec![Ok(Bytes::from(vec![byte; size]))])
+  }
+
+  #[actix_web::test]
+  async fn test_check() {
+    let dir = std::env::temp_dir().join("turbo-remote-cache-check");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config::default()
+      .with_storage_provider(StorageProvider::File)
+      .with_fs_cache_path(dir.to_str().unwrap().to_string());
+    check(&config).await.unwrap();
+    let storage = StorageStore::new(&config).unwrap();
+    assert!(!storage.exists(CHECK_PATH).await.unwrap());
+  }
+
+  #[actix_web::test]
+  async fn test_get_racing_an_overwrite() {
+    let dir = std::env::temp_dir().join("turbo-remote-cache-overwrite");
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config::default()
+      .with_storage_provider(StorageProvider::File)
+      .with_fs_cache_path(dir.to_str().unwrap().to_string());
+    let storage = StorageStore::new(&config).unwrap();
+    let metadata = ArtifactMetadata::default();
+    let size = 2 * DEFAULT_VERIFIED_SIZE;
+    storage
+      .put_stream("team/123", body(b'a', size), &metadata)
+      .await
+      .unwrap();
+
+    // overwritten while the first version is streamed
+    let (artifact, _) = storage.get("team/123").await.unwrap();
+    storage
+      .put_stream("team/123", body(b'b', size), &metadata)
+      .await
+      .unwrap();
+    let data: Vec<Bytes> = artifact.stream.try_collect().await.unwrap();
+    assert_eq!(data.concat(), vec![b'a'; size]);
+
+    // read between an overwrite of the object and of its metadata
+    let path = Path::from("team/123");
+    let new_object = |byte, size| storage.write_stream("team/123", body(byte, size));
+    new_object(b'c', size / 2).await.unwrap();
+    assert!(matches!(
+      storage.get("team/123").await,
+      Err(Error::Corrupted(_))
+    ));
+    new_object(b'c', size).await.unwrap();
+    let (artifact, _) = storage.get("team/123").await.unwrap();
+    let data: Result<Vec<Bytes>, Error> = artifact.stream.try_collect().await;
+    assert!(matches!(data, Err(Error::Corrupted(_))));
+    actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+    assert!(storage.object_store.head(&path).await.is_ok());
+    assert!(!dir.join("cache/.quarantine").exists());
+  }
+
+  #[actix_web::test]
+  async fn test_verified_size() {
+    let dir = std::env::temp_dir().join("turbo-remote-cache-verified-size");
+    let _ = std::fs::remove_dir_all(&dir);
+    let size = 2 * DEFAULT_VERIFIED_SIZE;
+    let config = Config::default()
+      .with_storage_provider(StorageProvider::File)
+      .with_fs_cache_path(dir.to_str().unwrap().to_string());
+    StorageStore::new(&config)
+      .unwrap()
+      .put_stream("team/123", body(b'a', size), &ArtifactMetadata::default())
+      .await
+      .unwrap();
+    let object = dir.join("cache/team/123");
+    let mut stored = std::fs::read(&object).unwrap();
+    stored[size - 1] = b'b';
+    std::fs::write(&object, stored).unwrap();
+
+    // the corruption is past the verified prefix, it's only seen once streamed
+    let (artifact, _) = StorageStore::new(&config)
+      .unwrap()
+      .get("team/123")
+      .await
+      .unwrap();
+    let data: Result<Vec<Bytes>, Error> = artifact.stream.try_collect().await;
+    assert!(matches!(data, Err(Error::Corrupted(_))));
+
+    let storage = StorageStore::new(&config.with_verified_size(size)).unwrap();
+    assert!(matches!(
+      storage.get("team/123").await,
+      Err(Error::Corrupted(_))
+    ));
+  }
+}
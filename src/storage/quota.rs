@@ -0,0 +1,547 @@
+use std::{
+  cell::RefCell,
+  collections::HashMap,
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use futures_util::TryStreamExt;
+use log::info;
+use object_store::{path::Path, ObjectMeta};
+
+use super::{StorageStore, METADATA_SUFFIX};
+use crate::{config::TeamQuota, error::Error};
+
+// Usage is tracked incrementally, it is listed again past this age to account
+// for artifacts removed by the garbage collection or uploaded by other replicas.
+const USAGE_TTL: Duration = Duration::from_secs(600);
+
+/// Bytes and number of artifacts stored by a team.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+  pub bytes: u64,
+  pub objects: u64,
+}
+
+struct TrackedUsage {
+  usage: Usage,
+  // when the listing started, artifacts completed since aren't counted
+  listed: Instant,
+  // an upload completed while the usage was listed, it may or may not be counted
+  stale: bool,
+}
+
+/// Quotas of the teams and the usage of the teams having one.
+pub struct Quotas {
+  limits: Vec<TeamQuota>,
+  evict: bool,
+  usage: Mutex<HashMap<String, TrackedUsage>>,
+  // bytes and artifacts of the uploads in flight, kept when the usage is listed again
+  reserved: Mutex<HashMap<String, Usage>>,
+}
+
+impl Quotas {
+  /// `None` when no quota is configured.
+  pub fn new(limits: &[TeamQuota], evict: bool) -> Option<Self> {
+    if limits.is_empty() {
+      return None;
+    }
+    Some(Quotas {
+      limits: limits.to_vec(),
+      evict,
+      usage: Mutex::new(HashMap::new()),
+      reserved: Mutex::new(HashMap::new()),
+    })
+  }
+
+  fn limit(&self, team_id: &str) -> Option<&TeamQuota> {
+    let own = self
+      .limits
+      .iter()
+      .find(|quota| quota.team.as_deref() == Some(team_id));
+    own.or_else(|| self.limits.iter().find(|quota| quota.team.is_none()))
+  }
+
+  fn cached(&self, team_id: &str) -> Option<Usage> {
+    let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+    usage
+      .get(team_id)
+      .filter(|tracked| !tracked.stale && tracked.listed.elapsed() < USAGE_TTL)
+      .map(|tracked| tracked.usage)
+  }
+
+  // Applies `update` for an artifact whose upload started completing at `written`,
+  // unless the usage was listed since: the listing may already count it, the usage
+  // is then listed again when next needed.
+  fn update(&self, team_id: &str, written: Instant, update: impl FnOnce(&mut Usage)) {
+    let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+    match usage.get_mut(team_id) {
+      Some(tracked) if tracked.listed >= written => tracked.stale = true,
+      Some(tracked) => update(&mut tracked.usage),
+      None => {}
+    }
+  }
+
+  fn store(&self, team_id: &str, usage: Usage, listed: Instant) {
+    let tracked = TrackedUsage {
+      usage,
+      listed,
+      stale: false,
+    };
+    let mut teams = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+    teams.insert(team_id.to_string(), tracked);
+  }
+
+  fn pending(&self, team_id: &str) -> Usage {
+    let reserved = self.reserved.lock().unwrap_or_else(|e| e.into_inner());
+    reserved.get(team_id).copied().unwrap_or_default()
+  }
+
+  /// Drops the tracked usage of `team_id`, it is listed again when next needed.
+  pub fn forget(&self, team_id: &str) {
+    let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+    usage.remove(team_id);
+  }
+}
+
+// Team owning the artifact at `path`, i.e. `team_id/hash`.
+fn team_of(path: &str) -> &str {
+  path.split_once('/').map_or(path, |(team, _)| team)
+}
+
+fn total(artifacts: &[ObjectMeta]) -> Usage {
+  Usage {
+    bytes: artifacts.iter().map(|meta| meta.size as u64).sum(),
+    objects: artifacts.len() as u64,
+  }
+}
+
+fn add(usage: Usage, other: Usage) -> Usage {
+  Usage {
+    bytes: usage.bytes + other.bytes,
+    objects: usage.objects + other.objects,
+  }
+}
+
+// Whether storing `added` on top of `usage` goes over `quota`, the `previous` bytes
+// of the artifact it overwrites being freed.
+fn exceeded(quota: &TeamQuota, usage: Usage, added: Usage, previous: Option<u64>) -> bool {
+  let bytes = (usage.bytes + added.bytes).saturating_sub(previous.unwrap_or_default());
+  quota.max_bytes.is_some_and(|max| bytes > max)
+    || quota
+      .max_objects
+      .is_some_and(|max| usage.objects + added.objects > max)
+}
+
+/// Bytes and artifact reserved in the quota of a team by an upload in flight, so
+/// concurrent uploads can't overrun it together. The reservation is released when
+/// dropped, unless the upload is recorded.
+pub(super) struct Reservation<'a> {
+  quotas: &'a Quotas,
+  quota: &'a TeamQuota,
+  team_id: String,
+  // size of the artifact being overwritten, freed once the upload is recorded
+  previous: Option<u64>,
+  reserved: Usage,
+}
+
+impl Reservation<'_> {
+  // Reserves `added` more, failing when it doesn't fit along the usage of the team
+  // and the uploads in flight, this one included.
+  fn reserve(&mut self, added: Usage) -> Result<(), Error> {
+    let usage = self.quotas.usage.lock().unwrap_or_else(|e| e.into_inner());
+    let mut reserved = self
+      .quotas
+      .reserved
+      .lock()
+      .unwrap_or_else(|e| e.into_inner());
+    let stored = usage
+      .get(&self.team_id)
+      .map(|tracked| tracked.usage)
+      .unwrap_or_default();
+    let pending = reserved.entry(self.team_id.clone()).or_default();
+    if exceeded(self.quota, add(stored, *pending), added, self.previous) {
+      return Err(Error::QuotaExceeded(format!(
+        "Artifact exceeds the remaining quota of team {}",
+        self.team_id
+      )));
+    }
+    *pending = add(*pending, added);
+    self.reserved = add(self.reserved, added);
+    Ok(())
+  }
+
+  /// Reserves `bytes` more as they are stored.
+  pub(super) fn add(&mut self, bytes: u64) -> Result<(), Error> {
+    self.reserve(Usage { bytes, objects: 0 })
+  }
+
+  /// Accounts for the stored artifact in the usage of the team, in place of the
+  /// one it overwrote, its upload having started completing at `written`.
+  pub(super) fn record(self, written: Instant) {
+    let (reserved, previous) = (self.reserved, self.previous);
+    self.quotas.update(&self.team_id, written, |usage| {
+      *usage = add(*usage, reserved);
+      usage.bytes = usage.bytes.saturating_sub(previous.unwrap_or_default());
+    });
+  }
+}
+
+impl Drop for Reservation<'_> {
+  fn drop(&mut self) {
+    let mut reserved = self
+      .quotas
+      .reserved
+      .lock()
+      .unwrap_or_else(|e| e.into_inner());
+    if let Some(pending) = reserved.get_mut(&self.team_id) {
+      pending.bytes = pending.bytes.saturating_sub(self.reserved.bytes);
+      pending.objects = pending.objects.saturating_sub(self.reserved.objects);
+      if *pending == Usage::default() {
+        reserved.remove(&self.team_id);
+      }
+    }
+  }
+}
+
+impl StorageStore {
+  /// Checks an upload of `length` bytes (when known) to `path` fits in the quota
+  /// of its team, evicting its least recently written artifacts when eviction is
+  /// on. Quotas count stored bytes, which are only known once an artifact is
+  /// compressed: the upload is checked again by [`StorageStore::put_stream`] as
+  /// it is stored, evicting more artifacts then when needed.
+  pub async fn check_quota(&self, path: &str, length: Option<u64>) -> Result<(), Error> {
+    let Some(quotas) = &self.quotas else {
+      return Ok(());
+    };
+    let team_id = team_of(path);
+    let Some(quota) = quotas.limit(team_id) else {
+      return Ok(());
+    };
+    let length = match self.compression_level {
+      Some(_) => 0,
+      None => length.unwrap_or_default(),
+    };
+    if let Some(max) = quota.max_bytes.filter(|max| length > *max) {
+      return Err(Error::PayloadTooLarge(format!(
+        "Artifact exceeds the {} bytes quota of team {}",
+        max, team_id
+      )));
+    }
+    let previous = self.overwritten_size(path).await?;
+    let added = Usage {
+      bytes: length,
+      objects: previous.is_none() as u64,
+    };
+    let mut usage = add(self.usage(team_id).await?, quotas.pending(team_id));
+    if exceeded(quota, usage, added, previous) && quotas.evict {
+      usage = self.evict(path, quota, added, previous).await?;
+    }
+    if exceeded(quota, usage, added, previous) {
+      return Err(Error::QuotaExceeded(format!(
+        "Team {} is over its quota, it stores {} bytes in {} artifacts",
+        team_id, usage.bytes, usage.objects
+      )));
+    }
+    Ok(())
+  }
+
+  // Reserves `bytes` more for the upload to `path` as they are stored, evicting the
+  // least recently written artifacts of its team when they don't fit and eviction
+  // is on, e.g. for compressed uploads checked up front with no length.
+  pub(super) async fn reserve_stored(
+    &self,
+    path: &str,
+    reservation: &RefCell<Reservation<'_>>,
+    bytes: u64,
+  ) -> Result<(), Error> {
+    let result = reservation.borrow_mut().add(bytes);
+    match result {
+      Err(Error::QuotaExceeded(_)) if reservation.borrow().quotas.evict => {
+        let (quota, previous) = {
+          let reservation = reservation.borrow();
+          (reservation.quota, reservation.previous)
+        };
+        let added = Usage { bytes, objects: 0 };
+        self.evict(path, quota, added, previous).await?;
+        reservation.borrow_mut().add(bytes)
+      }
+      result => result,
+    }
+  }
+
+  // Starts reserving the quota of the team owning `path` for an upload to it,
+  // `None` when the team has no quota.
+  pub(super) async fn reserve(&self, path: &str) -> Result<Option<Reservation<'_>>, Error> {
+    let Some(quotas) = &self.quotas else {
+      return Ok(None);
+    };
+    let team_id = team_of(path);
+    let Some(quota) = quotas.limit(team_id) else {
+      return Ok(None);
+    };
+    // the usage must be tracked for uploads to be accounted for
+    self.usage(team_id).await?;
+    let previous = self.overwritten_size(path).await?;
+    let mut reservation = Reservation {
+      quotas,
+      quota,
+      team_id: team_id.to_string(),
+      previous,
+      reserved: Usage::default(),
+    };
+    reservation.reserve(Usage {
+      bytes: 0,
+      objects: previous.is_none() as u64,
+    })?;
+    Ok(Some(reservation))
+  }
+
+  /// Returns the bytes and number of artifacts stored by `team_id`.
+  pub async fn usage(&self, team_id: &str) -> Result<Usage, Error> {
+    if let Some(usage) = self.quotas.as_ref().and_then(|q| q.cached(team_id)) {
+      return Ok(usage);
+    }
+    let listed = Instant::now();
+    let usage = total(&self.list_team(team_id).await?);
+    if let Some(quotas) = &self.quotas {
+      quotas.store(team_id, usage, listed);
+    }
+    Ok(usage)
+  }
+
+  // Lists the artifacts of `team_id`, without their metadata sidecars.
+  pub(super) async fn list_team(&self, team_id: &str) -> Result<Vec<ObjectMeta>, Error> {
+    let prefix = Path::from(team_id);
+    let artifacts = self
+      .object_store
+      .list(Some(&prefix))
+      .try_filter(|meta| {
+        let sidecar = meta.location.as_ref().ends_with(METADATA_SUFFIX);
+        futures_util::future::ready(!sidecar)
+      })
+      .try_collect()
+      .await?;
+    Ok(artifacts)
+  }
+
+  // Deletes the least recently written artifacts of the team owning `path` until
+  // an upload to it of `added` fits in `quota`, returns the usage left along the
+  // uploads in flight. The artifact at `path` is kept, it's about to be replaced.
+  async fn evict(
+    &self,
+    path: &str,
+    quota: &TeamQuota,
+    added: Usage,
+    previous: Option<u64>,
+  ) -> Result<Usage, Error> {
+    let team_id = team_of(path);
+    let listed = Instant::now();
+    let mut artifacts = self.list_team(team_id).await?;
+    artifacts.sort_by_key(|meta| meta.last_modified);
+    let mut usage = total(&artifacts);
+    let pending = self
+      .quotas
+      .as_ref()
+      .map(|quotas| quotas.pending(team_id))
+      .unwrap_or_default();
+    for meta in artifacts {
+      if !exceeded(quota, add(usage, pending), added, previous) {
+        break;
+      }
+      if meta.location.as_ref() == path {
+        continue;
+      }
+      self.delete(meta.location.as_ref()).await?;
+      info!(
+        "Evicted {} ({} bytes) to fit the quota of team {}",
+        meta.location, meta.size, team_id
+      );
+      usage.bytes -= meta.size as u64;
+      usage.objects -= 1;
+    }
+    if let Some(quotas) = &self.quotas {
+      quotas.store(team_id, usage, listed);
+    }
+    Ok(add(usage, pending))
+  }
+
+  // Whether the team owning `path` has a quota, its usage being tracked then.
+  pub(super) fn has_quota(&self, path: &str) -> bool {
+    self
+      .quotas
+      .as_ref()
+      .is_some_and(|quotas| quotas.limit(team_of(path)).is_some())
+  }
+
+  // Size of the artifact about to be overwritten at `path`, only looked up when
+  // the usage of its team is tracked.
+  pub(super) async fn overwritten_size(&self, path: &str) -> Result<Option<u64>, Error> {
+    if !self.has_quota(path) {
+      return Ok(None);
+    }
+    match self.object_store.head(&Path::from(path)).await {
+      Ok(meta) => Ok(Some(meta.size as u64)),
+      Err(object_store::Error::NotFound { .. }) => Ok(None),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  // Drops the tracked usage of the team owning `path`, after artifacts were deleted.
+  pub(super) fn forget_usage(&self, path: &str) {
+    if let Some(quotas) = &self.quotas {
+      quotas.forget(team_of(path));
+    }
+  }
+}
+
+#[cfg(test)]
+mod quota_tests {
+  use actix_web::web::Bytes;
+
+  use super::*;
+  use crate::{config::Config, storage::ArtifactMetadata};
+
+  fn storage(quota: &str, evict: bool) -> StorageStore {
+    let config = Config::default().with_quotas(vec![quota.parse().unwrap()], evict);
+    StorageStore::new(&config).unwrap()
+  }
+
+  async fn upload(storage: &StorageStore, path: &str, data: &'static [u8]) {
+    let length = data.len() as u64;
+    storage.check_quota(path, Some(length)).await.unwrap();
+    let body = futures_util::stream::iter(vec![Ok(Bytes::from_static(data))]);
+    storage
+      .put_stream(path, body, &ArtifactMetadata::default())
+      .await
+      .unwrap();
+  }
+
+  #[actix_web::test]
+  async fn test_quota_is_enforced() {
+    let storage = storage("*:20:3", false);
+    upload(&storage, "team/a", b"aaaa").await;
+    upload(&storage, "team/b", b"bbbb").await;
+    // overwriting an artifact replaces its size
+    upload(&storage, "team/b", b"bbbbbbbb").await;
+    assert_eq!(
+      storage.usage("team").await.unwrap(),
+      Usage {
+        bytes: 12,
+        objects: 2
+      }
+    );
+    assert!(storage.check_quota("team/c", Some(8)).await.is_ok());
+    assert!(matches!(
+      storage.check_quota("team/c", Some(9)).await,
+      Err(Error::QuotaExceeded(_))
+    ));
+    assert!(matches!(
+      storage.check_quota("team/c", Some(21)).await,
+      Err(Error::PayloadTooLarge(_))
+    ));
+    // other teams have their own usage
+    assert!(storage.check_quota("other/c", Some(20)).await.is_ok());
+  }
+
+  #[actix_web::test]
+  async fn test_quota_overwrite() {
+    let storage = storage("team:8:1", false);
+    upload(&storage, "team/a", b"aaaa").await;
+    // the overwritten artifact is freed
+    upload(&storage, "team/a", b"aaaaaaaa").await;
+    assert_eq!(
+      storage.usage("team").await.unwrap(),
+      Usage {
+        bytes: 8,
+        objects: 1
+      }
+    );
+    assert!(matches!(
+      storage.check_quota("team/b", Some(0)).await,
+      Err(Error::QuotaExceeded(_))
+    ));
+  }
+
+  #[actix_web::test]
+  async fn test_quota_reserves_uploads_in_flight() {
+    let storage = storage("team:10", false);
+    let mut reservation = storage.reserve("team/a").await.unwrap().unwrap();
+    reservation.add(6).unwrap();
+    assert!(matches!(
+      storage.check_quota("team/b", Some(6)).await,
+      Err(Error::QuotaExceeded(_))
+    ));
+    let mut other = storage.reserve("team/b").await.unwrap().unwrap();
+    assert!(matches!(other.add(6), Err(Error::QuotaExceeded(_))));
+    other.add(4).unwrap();
+    drop(other);
+
+    // released when the upload fails, settled once it's recorded
+    drop(reservation);
+    assert!(storage.check_quota("team/b", Some(10)).await.is_ok());
+    let mut reservation = storage.reserve("team/a").await.unwrap().unwrap();
+    reservation.add(6).unwrap();
+    reservation.record(Instant::now());
+    assert_eq!(
+      storage.usage("team").await.unwrap(),
+      Usage {
+        bytes: 6,
+        objects: 1
+      }
+    );
+    assert!(storage.check_quota("team/b", Some(4)).await.is_ok());
+    assert!(storage.check_quota("team/b", Some(5)).await.is_err());
+  }
+
+  #[actix_web::test]
+  async fn test_quota_evicts_oldest() {
+    let storage = storage("team:10", true);
+    upload(&storage, "team/a", b"aaaa").await;
+    actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+    upload(&storage, "team/b", b"bbbb").await;
+    upload(&storage, "team/c", b"cccc").await;
+    assert!(!storage.exists("team/a").await.unwrap());
+    assert!(storage.exists("team/b").await.unwrap());
+    assert!(storage.exists("team/c").await.unwrap());
+    assert_eq!(storage.usage("team").await.unwrap().bytes, 8);
+  }
+
+  #[actix_web::test]
+  async fn test_quota_evicts_uploads_of_unknown_length() {
+    let storage = storage("team:10", true);
+    upload(&storage, "team/a", b"aaaa").await;
+    actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+    upload(&storage, "team/b", b"bbbb").await;
+    // only the stored bytes tell the upload doesn't fit
+    storage.check_quota("team/c", None).await.unwrap();
+    let body = futures_util::stream::iter(vec![Ok(Bytes::from_static(b"cccc"))]);
+    storage
+      .put_stream("team/c", body, &ArtifactMetadata::default())
+      .await
+      .unwrap();
+    assert!(!storage.exists("team/a").await.unwrap());
+    assert!(storage.exists("team/c").await.unwrap());
+    assert_eq!(storage.usage("team").await.unwrap().bytes, 8);
+  }
+
+  #[actix_web::test]
+  async fn test_quota_relisted_during_an_upload() {
+    let storage = storage("team:10", false);
+    let mut reservation = storage.reserve("team/a").await.unwrap().unwrap();
+    reservation.add(4).unwrap();
+    let body = futures_util::stream::iter(vec![Ok(Bytes::from_static(b"aaaa"))]);
+    let written = storage.write_stream("team/a", body).await.unwrap();
+    // listed once the artifact is stored, before the upload is recorded
+    storage.forget_usage("team/a");
+    storage.usage("team").await.unwrap();
+    reservation.record(written);
+    assert_eq!(
+      storage.usage("team").await.unwrap(),
+      Usage {
+        bytes: 4,
+        objects: 1
+      }
+    );
+  }
+}
@@ -0,0 +1,192 @@
+use std::{
+  fmt::Display,
+  sync::Arc,
+  time::{Duration, SystemTime},
+};
+
+use log::{error, info};
+
+use super::StorageStore;
+use crate::{
+  config::{Config, RetentionRule},
+  error::Error,
+};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RetentionReport {
+  pub teams: usize,
+  /// Artifacts removed (or that would be, in dry-run mode).
+  pub deleted: Vec<String>,
+  pub reclaimed_bytes: u64,
+}
+
+impl Display for RetentionReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "removed {} artifacts ({} bytes) from {} teams",
+      self.deleted.len(),
+      self.reclaimed_bytes,
+      self.teams
+    )
+  }
+}
+
+/// Removes the artifacts of the teams matching a [`RetentionRule`] once they are
+/// past the limits of the first matching rule.
+pub struct RetentionSweeper {
+  storage: Arc<StorageStore>,
+  rules: Vec<RetentionRule>,
+  dry_run: bool,
+  interval: Duration,
+}
+
+impl RetentionSweeper {
+  /// Builds the sweeper for `config`, `None` when no retention rule is configured.
+  pub fn from_config(config: &Config, storage: Arc<StorageStore>, dry_run: bool) -> Option<Self> {
+    if config.retention_rules.is_empty() {
+      return None;
+    }
+    Some(RetentionSweeper {
+      storage,
+      rules: config.retention_rules.clone(),
+      dry_run: dry_run || config.retention_dry_run,
+      interval: config.retention_interval,
+    })
+  }
+
+  /// Runs a single sweep over every team and reports what was (or would be, in
+  /// dry-run mode) removed.
+  pub async fn run(&self) -> Result<RetentionReport, Error> {
+    let mut report = RetentionReport::default();
+    for team_id in self.storage.teams().await? {
+      if let Some(rule) = self.rules.iter().find(|rule| rule.matches(&team_id)) {
+        report.teams += 1;
+        self.sweep(&team_id, rule, &mut report).await?;
+      }
+    }
+    Ok(report)
+  }
+
+  async fn sweep(
+    &self,
+    team_id: &str,
+    rule: &RetentionRule,
+    report: &mut RetentionReport,
+  ) -> Result<(), Error> {
+    let mut artifacts = self.storage.list_team(team_id).await?;
+    // most recently written first, they are the ones kept
+    artifacts.sort_by_key(|meta| std::cmp::Reverse(meta.last_modified));
+    let now = SystemTime::now();
+    let mut kept_bytes: u64 = 0;
+    for (index, meta) in artifacts.into_iter().enumerate() {
+      let size = meta.size as u64;
+      let age = now
+        .duration_since(meta.last_modified.into())
+        .unwrap_or_default();
+      let expired = rule.max_age.is_some_and(|max_age| age > max_age);
+      let oversized = rule
+        .max_size
+        .is_some_and(|max_size| kept_bytes + size > max_size);
+      let surplus = rule.keep_last.is_some_and(|keep_last| index >= keep_last);
+      if !expired && !oversized && !surplus {
+        kept_bytes += size;
+        continue;
+      }
+      let location = meta.location.to_string();
+      if self.dry_run {
+        info!(
+          "Would remove {} ({} bytes, written {:?} ago)",
+          location, size, age
+        );
+      } else {
+        self.storage.delete(&location).await?;
+        info!(
+          "Removed {} ({} bytes, written {:?} ago)",
+          location, size, age
+        );
+      }
+      report.deleted.push(location);
+      report.reclaimed_bytes += size;
+    }
+    Ok(())
+  }
+
+  /// Runs the sweeper every `interval` in the background.
+  pub fn spawn(self) {
+    actix_web::rt::spawn(async move {
+      let mut interval = actix_web::rt::time::interval(self.interval);
+      loop {
+        interval.tick().await;
+        match self.run().await {
+          Ok(report) => info!("Retention sweep: {}", report),
+          Err(e) => error!("Retention sweep failed: {}", e),
+        }
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod retention_tests {
+  use actix_web::web::Bytes;
+
+  use super::*;
+
+  async fn put(storage: &StorageStore, path: &str, size: usize) {
+    storage
+      .put(path, Bytes::from(vec![0u8; size]))
+      .await
+      .unwrap();
+    actix_web::rt::time::sleep(Duration::from_millis(5)).await;
+  }
+
+  fn sweeper(storage: &Arc<StorageStore>, rules: &[&str], dry_run: bool) -> RetentionSweeper {
+    let rules = rules.iter().map(|rule| rule.parse().unwrap()).collect();
+    let config = Config::default().with_retention_rules(rules);
+    RetentionSweeper::from_config(&config, storage.clone(), dry_run).unwrap()
+  }
+
+  #[actix_web::test]
+  async fn test_retention_rules() {
+    let storage = Arc::new(StorageStore::new(&Config::default()).unwrap());
+    for path in [
+      "pr-1/a",
+      "pr-1/b",
+      "pr-1/c",
+      "release/a",
+      "release/b",
+      "other/a",
+    ] {
+      put(&storage, path, 4).await;
+    }
+    let rules = ["pr-*:7d::1", "release:90d:4", "nightly:1d"];
+
+    let report = sweeper(&storage, &rules, true).run().await.unwrap();
+    assert_eq!(report.deleted.len(), 3);
+    assert!(storage.exists("pr-1/a").await.unwrap());
+
+    let report = sweeper(&storage, &rules, false).run().await.unwrap();
+    assert_eq!(
+      report,
+      RetentionReport {
+        teams: 2,
+        deleted: vec![
+          "pr-1/b".to_string(),
+          "pr-1/a".to_string(),
+          "release/a".to_string()
+        ],
+        reclaimed_bytes: 12,
+      }
+    );
+    for (path, exists) in [
+      ("pr-1/c", true),
+      ("pr-1/b", false),
+      ("release/b", true),
+      ("release/a", false),
+      ("other/a", true),
+    ] {
+      assert_eq!(storage.exists(path).await.unwrap(), exists, "{}", path);
+    }
+  }
+}
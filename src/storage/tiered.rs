@@ -0,0 +1,474 @@
+use std::{
+  fmt::Display,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+
+use async_trait::async_trait;
+use futures_util::{stream::BoxStream, FutureExt, StreamExt};
+use log::{debug, warn};
+use object_store::{
+  path::Path, GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore,
+  PutMultipartOpts, PutOptions, PutPayload, PutResult, Result, UploadPart, WriteMultipart,
+};
+
+use super::MAX_CONCURRENT_PARTS;
+use crate::{config::WritePolicy, metrics::TIER_REQUESTS};
+
+/// Streams the object at `location` from one store into another without holding
+/// it in memory as a whole.
+pub(super) async fn copy_object(
+  from: &dyn ObjectStore,
+  to: &dyn ObjectStore,
+  location: &Path,
+) -> Result<()> {
+  let mut stream = from.get(location).await?.into_stream();
+  let mut writer = WriteMultipart::new(to.put_multipart(location).await?);
+  while let Some(chunk) = stream.next().await {
+    let chunk = match chunk {
+      Ok(chunk) => chunk,
+      Err(e) => {
+        writer.abort().await?;
+        return Err(e);
+      }
+    };
+    if let Err(e) = writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await {
+      writer.abort().await?;
+      return Err(e);
+    }
+    writer.put(chunk);
+  }
+  writer.finish().await?;
+  Ok(())
+}
+
+// `GetOptions` isn't `Clone`, a miss needs the same options for both tiers.
+fn copy_options(options: &GetOptions) -> GetOptions {
+  GetOptions {
+    if_match: options.if_match.clone(),
+    if_none_match: options.if_none_match.clone(),
+    if_modified_since: options.if_modified_since,
+    if_unmodified_since: options.if_unmodified_since,
+    range: options.range.clone(),
+    version: options.version.clone(),
+    head: options.head,
+  }
+}
+
+// Removes `location` from the local tier, it being already gone is fine.
+async fn evict(local: &dyn ObjectStore, location: &Path) {
+  match local.delete(location).await {
+    Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+    Err(e) => warn!("Failed to remove {} from the local tier: {}", location, e),
+  }
+}
+
+// Uploads the local copy of `location` to the remote store in the background.
+fn spawn_write_back(local: Arc<dyn ObjectStore>, remote: Arc<dyn ObjectStore>, location: Path) {
+  actix_web::rt::spawn(async move {
+    match copy_object(local.as_ref(), remote.as_ref(), &location).await {
+      Ok(()) => debug!("Wrote {} back to the remote store", location),
+      Err(e) => warn!(
+        "Failed to write {} back to the remote store: {}",
+        location, e
+      ),
+    }
+  });
+}
+
+/// A bounded local store (memory or disk) in front of a remote one.
+///
+/// Reads are served from the local tier and filled from the remote store on a miss.
+/// Writes go to both tiers according to the [`WritePolicy`], the remote store stays
+/// the source of truth for listings.
+#[derive(Debug)]
+pub struct TieredStore {
+  local: Arc<dyn ObjectStore>,
+  remote: Arc<dyn ObjectStore>,
+  policy: WritePolicy,
+}
+
+impl TieredStore {
+  pub fn new(
+    local: Arc<dyn ObjectStore>,
+    remote: Arc<dyn ObjectStore>,
+    policy: WritePolicy,
+  ) -> Self {
+    TieredStore {
+      local,
+      remote,
+      policy,
+    }
+  }
+}
+
+impl Display for TieredStore {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Tiered({} -> {})", self.local, self.remote)
+  }
+}
+
+#[async_trait]
+impl ObjectStore for TieredStore {
+  async fn put_opts(
+    &self,
+    location: &Path,
+    payload: PutPayload,
+    opts: PutOptions,
+  ) -> Result<PutResult> {
+    match self.policy {
+      WritePolicy::WriteThrough => {
+        let result = self
+          .remote
+          .put_opts(location, payload.clone(), opts)
+          .await?;
+        if let Err(e) = self.local.put(location, payload).await {
+          warn!("Failed to write {} to the local tier: {}", location, e);
+          evict(self.local.as_ref(), location).await;
+        }
+        Ok(result)
+      }
+      WritePolicy::WriteBack => {
+        let result = self.local.put_opts(location, payload.clone(), opts).await?;
+        let remote = self.remote.clone();
+        let location = location.clone();
+        actix_web::rt::spawn(async move {
+          if let Err(e) = remote.put(&location, payload).await {
+            warn!(
+              "Failed to write {} back to the remote store: {}",
+              location, e
+            );
+          }
+        });
+        Ok(result)
+      }
+    }
+  }
+
+  async fn put_multipart_opts(
+    &self,
+    location: &Path,
+    opts: PutMultipartOpts,
+  ) -> Result<Box<dyn MultipartUpload>> {
+    match self.policy {
+      WritePolicy::WriteThrough => {
+        let remote = self.remote.put_multipart_opts(location, opts).await?;
+        let local = match self.local.put_multipart(location).await {
+          Ok(local) => Some(local),
+          Err(e) => {
+            warn!("Failed to write {} to the local tier: {}", location, e);
+            None
+          }
+        };
+        Ok(Box::new(WriteThroughUpload {
+          remote,
+          local,
+          local_store: self.local.clone(),
+          local_failed: Arc::new(AtomicBool::new(false)),
+          location: location.clone(),
+        }))
+      }
+      WritePolicy::WriteBack => {
+        let local = self.local.put_multipart_opts(location, opts).await?;
+        Ok(Box::new(WriteBackUpload {
+          local,
+          local_store: self.local.clone(),
+          remote: self.remote.clone(),
+          location: location.clone(),
+        }))
+      }
+    }
+  }
+
+  async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+    match self.local.get_opts(location, copy_options(&options)).await {
+      Ok(result) => {
+        TIER_REQUESTS.with_label_values(&["hit"]).inc();
+        return Ok(result);
+      }
+      Err(object_store::Error::NotFound { .. }) => {
+        TIER_REQUESTS.with_label_values(&["miss"]).inc();
+      }
+      Err(
+        e @ (object_store::Error::NotModified { .. } | object_store::Error::Precondition { .. }),
+      ) => return Err(e),
+      Err(e) => warn!("Failed to read {} from the local tier: {}", location, e),
+    }
+    if options.head {
+      return self.remote.get_opts(location, options).await;
+    }
+    if let Err(e) = copy_object(self.remote.as_ref(), self.local.as_ref(), location).await {
+      if matches!(e, object_store::Error::NotFound { .. }) {
+        return Err(e);
+      }
+      warn!("Failed to fill {} into the local tier: {}", location, e);
+      return self.remote.get_opts(location, options).await;
+    }
+    // the local tier may have evicted the object already if it's larger than its budget
+    match self.local.get_opts(location, copy_options(&options)).await {
+      Ok(result) => Ok(result),
+      Err(_) => self.remote.get_opts(location, options).await,
+    }
+  }
+
+  async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+    match self.local.head(location).await {
+      Ok(meta) => Ok(meta),
+      Err(_) => self.remote.head(location).await,
+    }
+  }
+
+  async fn delete(&self, location: &Path) -> Result<()> {
+    evict(self.local.as_ref(), location).await;
+    self.remote.delete(location).await
+  }
+
+  fn delete_stream<'a>(
+    &'a self,
+    locations: BoxStream<'a, Result<Path>>,
+  ) -> BoxStream<'a, Result<Path>> {
+    let evicted = locations.then(move |location| async move {
+      if let Ok(location) = &location {
+        evict(self.local.as_ref(), location).await;
+      }
+      location
+    });
+    self.remote.delete_stream(evicted.boxed())
+  }
+
+  fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+    self.remote.list(prefix)
+  }
+
+  fn list_with_offset(
+    &self,
+    prefix: Option<&Path>,
+    offset: &Path,
+  ) -> BoxStream<'_, Result<ObjectMeta>> {
+    self.remote.list_with_offset(prefix, offset)
+  }
+
+  async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+    self.remote.list_with_delimiter(prefix).await
+  }
+
+  async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+    self.remote.copy(from, to).await?;
+    evict(self.local.as_ref(), to).await;
+    Ok(())
+  }
+
+  async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+    self.remote.copy_if_not_exists(from, to).await?;
+    evict(self.local.as_ref(), to).await;
+    Ok(())
+  }
+}
+
+// Sends every part to the remote store and, best effort, to the local tier.
+#[derive(Debug)]
+struct WriteThroughUpload {
+  remote: Box<dyn MultipartUpload>,
+  local: Option<Box<dyn MultipartUpload>>,
+  local_store: Arc<dyn ObjectStore>,
+  // set when a part couldn't be written locally, the local copy would be truncated
+  local_failed: Arc<AtomicBool>,
+  location: Path,
+}
+
+#[async_trait]
+impl MultipartUpload for WriteThroughUpload {
+  fn put_part(&mut self, data: PutPayload) -> UploadPart {
+    let local = self
+      .local
+      .as_mut()
+      .map(|local| local.put_part(data.clone()));
+    let remote = self.remote.put_part(data);
+    let location = self.location.clone();
+    let local_failed = self.local_failed.clone();
+    async move {
+      if let Some(local) = local {
+        if let Err(e) = local.await {
+          warn!("Failed to write {} to the local tier: {}", location, e);
+          local_failed.store(true, Ordering::Relaxed);
+        }
+      }
+      remote.await
+    }
+    .boxed()
+  }
+
+  async fn complete(&mut self) -> Result<PutResult> {
+    let result = self.remote.complete().await;
+    let mut stale = result.is_ok();
+    if let Some(mut local) = self.local.take() {
+      let local_result = match result.is_ok() && !self.local_failed.load(Ordering::Relaxed) {
+        true => local.complete().await.map(|_| stale = false),
+        false => local.abort().await,
+      };
+      if let Err(e) = local_result {
+        warn!("Failed to write {} to the local tier: {}", self.location, e);
+      }
+    }
+    // a local copy written before is outdated once the remote one was replaced
+    if stale {
+      evict(self.local_store.as_ref(), &self.location).await;
+    }
+    result
+  }
+
+  async fn abort(&mut self) -> Result<()> {
+    if let Some(mut local) = self.local.take() {
+      if let Err(e) = local.abort().await {
+        warn!("Failed to abort {} in the local tier: {}", self.location, e);
+      }
+    }
+    self.remote.abort().await
+  }
+}
+
+// Writes to the local tier and uploads the complete object in the background.
+#[derive(Debug)]
+struct WriteBackUpload {
+  local: Box<dyn MultipartUpload>,
+  local_store: Arc<dyn ObjectStore>,
+  remote: Arc<dyn ObjectStore>,
+  location: Path,
+}
+
+#[async_trait]
+impl MultipartUpload for WriteBackUpload {
+  fn put_part(&mut self, data: PutPayload) -> UploadPart {
+    self.local.put_part(data)
+  }
+
+  async fn complete(&mut self) -> Result<PutResult> {
+    let result = self.local.complete().await?;
+    spawn_write_back(
+      self.local_store.clone(),
+      self.remote.clone(),
+      self.location.clone(),
+    );
+    Ok(result)
+  }
+
+  async fn abort(&mut self) -> Result<()> {
+    self.local.abort().await
+  }
+}
+
+#[cfg(test)]
+mod tiered_tests {
+  use super::*;
+  use object_store::memory::InMemory;
+
+  fn stores(policy: WritePolicy) -> (Arc<InMemory>, Arc<InMemory>, TieredStore) {
+    let local = Arc::new(InMemory::new());
+    let remote = Arc::new(InMemory::new());
+    let tiered = TieredStore::new(local.clone(), remote.clone(), policy);
+    (local, remote, tiered)
+  }
+
+  async fn read(store: &dyn ObjectStore, location: &str) -> Option<Vec<u8>> {
+    let result = store.get(&Path::from(location)).await.ok()?;
+    Some(result.bytes().await.unwrap().to_vec())
+  }
+
+  #[actix_web::test]
+  async fn test_read_through_fills_local_tier() {
+    let (local, remote, tiered) = stores(WritePolicy::WriteThrough);
+    remote
+      .put(&Path::from("team/a"), PutPayload::from(vec![1u8; 8]))
+      .await
+      .unwrap();
+    assert_eq!(read(local.as_ref(), "team/a").await, None);
+    assert_eq!(read(&tiered, "team/a").await, Some(vec![1u8; 8]));
+    assert_eq!(read(local.as_ref(), "team/a").await, Some(vec![1u8; 8]));
+    assert!(tiered.get(&Path::from("team/b")).await.is_err());
+  }
+
+  #[actix_web::test]
+  async fn test_write_through() {
+    let (local, remote, tiered) = stores(WritePolicy::WriteThrough);
+    tiered
+      .put(&Path::from("team/a"), PutPayload::from(vec![1u8; 8]))
+      .await
+      .unwrap();
+    let mut upload = tiered.put_multipart(&Path::from("team/b")).await.unwrap();
+    upload.put_part(vec![2u8; 4].into()).await.unwrap();
+    upload.put_part(vec![2u8; 4].into()).await.unwrap();
+    upload.complete().await.unwrap();
+    for location in ["team/a", "team/b"] {
+      assert!(read(local.as_ref(), location).await.is_some());
+      assert!(read(remote.as_ref(), location).await.is_some());
+    }
+    tiered.delete(&Path::from("team/a")).await.unwrap();
+    assert_eq!(read(local.as_ref(), "team/a").await, None);
+    assert_eq!(read(remote.as_ref(), "team/a").await, None);
+  }
+
+  // A local upload whose parts can't be written, e.g. the disk is full.
+  #[derive(Debug)]
+  struct FailingUpload;
+
+  #[async_trait]
+  impl MultipartUpload for FailingUpload {
+    fn put_part(&mut self, _data: PutPayload) -> UploadPart {
+      async {
+        Err(object_store::Error::Generic {
+          store: "local",
+          source: "disk full".into(),
+        })
+      }
+      .boxed()
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+      panic!("a failed local upload must not be completed")
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+      Ok(())
+    }
+  }
+
+  #[actix_web::test]
+  async fn test_write_through_local_failure() {
+    let (local, remote, _) = stores(WritePolicy::WriteThrough);
+    let location = Path::from("team/a");
+    local
+      .put(&location, PutPayload::from(vec![0u8; 8]))
+      .await
+      .unwrap();
+    let mut upload = WriteThroughUpload {
+      remote: remote.put_multipart(&location).await.unwrap(),
+      local: Some(Box::new(FailingUpload)),
+      local_store: local.clone(),
+      local_failed: Arc::new(AtomicBool::new(false)),
+      location: location.clone(),
+    };
+    upload.put_part(vec![1u8; 8].into()).await.unwrap();
+    upload.complete().await.unwrap();
+    assert_eq!(read(remote.as_ref(), "team/a").await, Some(vec![1u8; 8]));
+    // the outdated local copy is dropped rather than served as a hit
+    assert_eq!(read(local.as_ref(), "team/a").await, None);
+  }
+
+  #[actix_web::test]
+  async fn test_write_back() {
+    let (local, remote, tiered) = stores(WritePolicy::WriteBack);
+    let mut upload = tiered.put_multipart(&Path::from("team/a")).await.unwrap();
+    upload.put_part(vec![1u8; 8].into()).await.unwrap();
+    upload.complete().await.unwrap();
+    assert_eq!(read(local.as_ref(), "team/a").await, Some(vec![1u8; 8]));
+    for _ in 0..100 {
+      if read(remote.as_ref(), "team/a").await.is_some() {
+        return;
+      }
+      actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("team/a was never written back to the remote store");
+  }
+}